@@ -0,0 +1,168 @@
+use crate::{
+    prefetch::Prefetched,
+    rewrite::Rule,
+    url::{self, Url},
+    Error, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+pub const CACHE_FILE: &str = "prefetch.json";
+
+/// Prefetched sources kept on disk, so unchanged revisions are not downloaded again
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Cache {
+    /// Prefetched sources by flake reference of an exact revision, these never expire
+    revs: BTreeMap<String, Prefetched>,
+    /// Revisions that branches and tags resolved to by flake reference
+    heads: BTreeMap<String, Head>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Head {
+    rev: String,
+    /// Seconds since the unix epoch
+    resolved_at: i64,
+}
+
+/// Summary of the cache for 'cache stats'
+pub struct Stats {
+    pub revs: usize,
+    pub heads: usize,
+    pub fresh_heads: usize,
+}
+
+impl Cache {
+    /// Location of the cache in the cache directory `dir`, see [`Session::cache_dir`](crate::Session::cache_dir)
+    pub fn path(dir: &Path) -> PathBuf {
+        dir.join(CACHE_FILE)
+    }
+
+    /// Read the cache from disk, an empty cache when there is none or it is unreadable
+    pub fn load(dir: &Path) -> Result<Cache> {
+        let path = Cache::path(dir);
+        if !path.exists() {
+            return Ok(Cache::default());
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(Error::read(&path))?;
+        match serde_json::from_str(&content) {
+            Ok(cache) => Ok(cache),
+            Err(err) => {
+                log::warn!("Ignoring unreadable prefetch cache {path:?}: {err}");
+                Ok(Cache::default())
+            }
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = Cache::path(dir);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_string(self)?).map_err(Error::write(path))
+    }
+
+    /// Cached source for `url`, branches and tags only while their resolved revision
+    /// is younger than `ttl` seconds
+    pub fn lookup(
+        &self,
+        url: &Url,
+        rules: &[Rule],
+        now: i64,
+        ttl: u64,
+    ) -> Result<Option<Prefetched>> {
+        let rev = match url.tag.as_deref() {
+            Some(tag) if url::is_rev(tag) => tag.to_string(),
+            _ => match self.heads.get(&url.flake_ref(rules)?) {
+                Some(head) if now - head.resolved_at < ttl as i64 => head.rev.clone(),
+                _ => return Ok(None),
+            },
+        };
+
+        Ok(self.revs.get(&rev_key(url, rules, &rev)?).cloned())
+    }
+
+    pub fn insert(&mut self, url: &Url, rules: &[Rule], pre: &Prefetched, now: i64) -> Result<()> {
+        let rev = &pre.locked.rev;
+        if !url.tag.as_deref().is_some_and(url::is_rev) {
+            let head = Head {
+                rev: rev.clone(),
+                resolved_at: now,
+            };
+            self.heads.insert(url.flake_ref(rules)?, head);
+        }
+        self.revs.insert(rev_key(url, rules, rev)?, pre.clone());
+        Ok(())
+    }
+
+    pub fn stats(&self, now: i64, ttl: u64) -> Stats {
+        Stats {
+            revs: self.revs.len(),
+            heads: self.heads.len(),
+            fresh_heads: self
+                .heads
+                .values()
+                .filter(|head| now - head.resolved_at < ttl as i64)
+                .count(),
+        }
+    }
+}
+
+fn rev_key(url: &Url, rules: &[Rule], rev: &str) -> Result<String> {
+    let url = Url {
+        tag: Some(rev.to_string()),
+        ..url.clone()
+    };
+    url.flake_ref(rules)
+}
+
+pub fn now() -> Result<i64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64)
+}
+
+/// Remove the prefetch cache from disk, returns whether there was one
+pub fn clear(dir: &Path) -> Result<bool> {
+    let path = Cache::path(dir);
+    if !path.exists() {
+        return Ok(false);
+    }
+    std::fs::remove_file(&path).map_err(Error::write(path))?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::prefetched;
+
+    #[test]
+    fn expiry() {
+        let rev = "0123456789abcdef0123456789abcdef01234567";
+        let head = Url::try_from("github:nixos/nixpkgs/nixos-unstable".to_string()).unwrap();
+        let pinned = Url {
+            tag: Some(rev.into()),
+            ..head.clone()
+        };
+
+        let mut cache = Cache::default();
+        assert!(cache.lookup(&head, &[], 1000, 60).unwrap().is_none());
+        cache.insert(&head, &[], &prefetched(rev), 1000).unwrap();
+
+        let hit = cache.lookup(&head, &[], 1059, 60).unwrap().unwrap();
+        assert_eq!(hit.locked.rev, rev);
+        assert!(cache.lookup(&head, &[], 1060, 60).unwrap().is_none());
+        assert!(cache.lookup(&head, &[], 1000, 0).unwrap().is_none());
+
+        // exact revisions never expire
+        assert!(cache.lookup(&pinned, &[], i64::MAX, 0).unwrap().is_some());
+
+        let stats = cache.stats(1030, 60);
+        assert_eq!((stats.revs, stats.heads, stats.fresh_heads), (1, 1, 1));
+    }
+}
@@ -0,0 +1,133 @@
+use crate::{
+    git::{self, Commit, Mirror},
+    history,
+    session::Session,
+    Result,
+};
+use std::{collections::BTreeMap, fmt::Write};
+
+/// Commits between two pinned revisions of a source
+#[derive(Debug)]
+pub struct Changelog {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+    pub commits: Vec<Commit>,
+}
+
+impl Changelog {
+    pub fn resolve(
+        session: &Session,
+        name: &str,
+        remote: &str,
+        old: &str,
+        new: &str,
+    ) -> Result<Self> {
+        let commits = Mirror::open(session, remote)?.log(old, new)?;
+        Ok(Self {
+            name: name.to_string(),
+            old: old.to_string(),
+            new: new.to_string(),
+            commits,
+        })
+    }
+
+    pub fn text(&self) -> String {
+        let mut buf = format!(
+            "{name} {old}..{new} ({count} commits)\n",
+            name = self.name,
+            old = git::short_rev(&self.old),
+            new = git::short_rev(&self.new),
+            count = self.commits.len()
+        );
+        for commit in &self.commits {
+            let _ = writeln!(
+                buf,
+                "  {rev} {summary} ({author})",
+                rev = commit.short_rev(),
+                summary = commit.summary,
+                author = commit.author
+            );
+        }
+        buf
+    }
+
+    /// Markdown summary, suitable for commit messages and pull requests
+    pub fn markdown(&self) -> String {
+        let mut buf = format!(
+            "### {name} `{old}` → `{new}`\n\n",
+            name = self.name,
+            old = git::short_rev(&self.old),
+            new = git::short_rev(&self.new),
+        );
+        for commit in &self.commits {
+            let _ = writeln!(
+                buf,
+                "- `{rev}` {summary} ({author})",
+                rev = commit.short_rev(),
+                summary = commit.summary,
+                author = commit.author
+            );
+        }
+        buf
+    }
+}
+
+/// Conventional commit message for a set of source changes,
+/// including the markdown changelog of each pin when available
+pub fn commit_message(
+    entries: &[history::Entry],
+    changelogs: &BTreeMap<String, Changelog>,
+) -> String {
+    let mut buf = match entries {
+        [entry] => format!("chore(pins): update {} {}\n", entry.name, entry.revs()),
+        _ => {
+            let names = entries.iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+            let mut buf = format!("chore(pins): update {}\n\n", names.join(", "));
+            for entry in entries {
+                let _ = writeln!(buf, "- {}: {}", entry.name, entry.revs());
+            }
+            buf
+        }
+    };
+
+    for entry in entries {
+        if let Some(changelog) = changelogs.get(&entry.name) {
+            buf += "\n";
+            buf += &changelog.markdown();
+        }
+    }
+
+    buf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{github, HASH};
+
+    fn entry(name: &str, old: &str, new: &str) -> history::Entry {
+        let fetcher = |rev: &str| github("o", name, rev, HASH);
+        history::Entry::new(name, Some(fetcher(old)), Some(fetcher(new)))
+    }
+
+    #[test]
+    fn message() {
+        let single = [entry("nixpkgs", "0a1b2c3d4e", "4d5e6f7a8b")];
+        assert_eq!(
+            commit_message(&single, &BTreeMap::new()),
+            "chore(pins): update nixpkgs 0a1b2c3 -> 4d5e6f7\n"
+        );
+
+        let multiple = [
+            entry("nixpkgs", "0a1b2c3d4e", "4d5e6f7a8b"),
+            entry("home-manager", "1111111111", "2222222222"),
+        ];
+        assert_eq!(
+            commit_message(&multiple, &BTreeMap::new()),
+            "chore(pins): update nixpkgs, home-manager\n\n\
+             - nixpkgs: 0a1b2c3 -> 4d5e6f7\n\
+             - home-manager: 1111111 -> 2222222\n"
+        );
+    }
+}
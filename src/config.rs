@@ -0,0 +1,256 @@
+use crate::{auth::Tokens, emit::Style, rewrite, url::Hosts, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = ".nixpins.toml";
+
+/// Project configuration, read from `.nixpins.toml`
+///
+/// For example:
+/// ```toml
+/// file = "nix/pins.nix"
+/// indent = 4
+/// style = "expanded"
+/// jobs = 8
+///
+/// [update]
+/// min-age = 7
+///
+/// [cache]
+/// head-ttl = 600
+///
+/// [hosts]
+/// "ghe.corp.example" = "github"
+///
+/// [tokens]
+/// "ghe.corp.example" = "ghp_..."
+///
+/// [[rewrite]]
+/// from = "https://github.com/"
+/// to = "https://git.mirror.corp/github/"
+///
+/// [workspace]
+/// members = ["services/*/pins.nix"]
+/// shared = ["nixpkgs"]
+/// ```
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Pins file, relative to the directory of the config file
+    pub file: PathBuf,
+    /// Indent width of the generated pins file
+    pub indent: usize,
+    /// Layout of the generated pins file, `compact` or `expanded`
+    pub style: Style,
+    /// Amount of sources to prefetch in parallel
+    pub jobs: usize,
+    /// Policies applied when updating pins
+    pub update: UpdatePolicy,
+    /// Reuse of prefetched sources
+    pub cache: CachePolicy,
+    /// Custom domains mapped to the provider they run
+    pub hosts: Hosts,
+    /// Access tokens by domain for private repositories,
+    /// overridden by `GITHUB_TOKEN` and `GITLAB_TOKEN`
+    #[serde(skip_serializing)]
+    pub tokens: Tokens,
+    /// Rules replacing the start of git remotes when fetching, e.g. to go through a mirror
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rewrite: Vec<rewrite::Rule>,
+    /// Pins files updated together by 'update --workspace'
+    pub workspace: Workspace,
+
+    /// Location the config was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Rules for adopting new revisions, inputs can override these individually
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UpdatePolicy {
+    /// Minimum age in days of a revision before it is adopted
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_age: Option<u64>,
+}
+
+/// Rules for reusing prefetched sources, exact revisions are always reused
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct CachePolicy {
+    /// Seconds a resolved branch or tag is reused before prefetching it again
+    pub head_ttl: u64,
+}
+
+/// Pins files of a monorepo that are updated together
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Workspace {
+    /// Glob patterns of pins files relative to the config file,
+    /// every file named like the pins file below the workspace root when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub members: Vec<String>,
+    /// Inputs that must be locked to the same revision in every pins file that has them
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub shared: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            file: PathBuf::from("pins.nix"),
+            indent: 2,
+            style: Style::default(),
+            jobs: 1,
+            update: UpdatePolicy::default(),
+            cache: CachePolicy::default(),
+            hosts: Hosts::default(),
+            tokens: Tokens::default(),
+            rewrite: vec![],
+            workspace: Workspace::default(),
+            path: None,
+        }
+    }
+}
+
+impl Config {
+    /// Search for a config file from the current directory upwards,
+    /// falling back to the default configuration
+    pub fn discover() -> Result<Config> {
+        let cwd = std::env::current_dir()?;
+        for dir in cwd.ancestors() {
+            let path = dir.join(CONFIG_FILE_NAME);
+            if path.is_file() {
+                return Config::load(&path);
+            }
+        }
+
+        Ok(Config::default())
+    }
+
+    /// Search for the pins file from the current directory up to the git repository root,
+    /// unless its location was set by a config file
+    pub fn locate_file(&mut self) -> Result<()> {
+        self.locate_file_from(&std::env::current_dir()?);
+        Ok(())
+    }
+
+    fn locate_file_from(&mut self, cwd: &Path) {
+        if self.path.is_some() || self.file.is_absolute() {
+            return;
+        }
+
+        for dir in cwd.ancestors() {
+            let path = dir.join(&self.file);
+            if path.is_file() {
+                self.file = path;
+                break;
+            }
+            if dir.join(".git").exists() {
+                break;
+            }
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Config> {
+        let content = std::fs::read_to_string(path).map_err(Error::read(path))?;
+        let mut config: Config = toml::from_str(&content).map_err(|err| Error::InvalidFile {
+            path: path.to_path_buf(),
+            source: Box::new(err),
+        })?;
+
+        if let Some(dir) = path.parent() {
+            config.file = dir.join(&config.file);
+        }
+        config.path = Some(path.to_path_buf());
+
+        Ok(config)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url::Provider;
+
+    #[test]
+    fn parse() {
+        let config: Config = toml::from_str(
+            r#"
+            indent = 4
+            style = "expanded"
+
+            [hosts]
+            "ghe.corp.example" = "github"
+            "git.corp.example" = "forgejo"
+
+            [workspace]
+            members = ["services/*/pins.nix"]
+
+            [[rewrite]]
+            from = "https://github.com/"
+            to = "https://git.mirror.corp/github/"
+            emit = true
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.file, PathBuf::from("pins.nix"));
+        assert_eq!(config.indent, 4);
+        assert_eq!(config.style, Style::Expanded);
+        assert_eq!(config.jobs, 1);
+        assert_eq!(
+            config.hosts,
+            Hosts::from([
+                ("ghe.corp.example".into(), Provider::Github),
+                ("git.corp.example".into(), Provider::Gitea),
+            ])
+        );
+        assert_eq!(config.workspace.members, ["services/*/pins.nix"]);
+        assert!(config.workspace.shared.is_empty());
+        assert_eq!(
+            config.rewrite,
+            [rewrite::Rule {
+                from: "https://github.com/".into(),
+                to: "https://git.mirror.corp/github/".into(),
+                emit: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn locate_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("repo");
+        let nested = root.join("services/api");
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(tmp.path().join("pins.nix"), "{ }").unwrap();
+
+        // the search stops at the git repository root
+        let located = |cwd: &Path| {
+            let mut config = Config::default();
+            config.locate_file_from(cwd);
+            config.file
+        };
+        assert_eq!(located(&nested), PathBuf::from("pins.nix"));
+
+        std::fs::write(root.join("pins.nix"), "{ }").unwrap();
+        assert_eq!(located(&nested), root.join("pins.nix"));
+        std::fs::write(nested.join("pins.nix"), "{ }").unwrap();
+        assert_eq!(located(&nested), nested.join("pins.nix"));
+        assert_eq!(located(&root.join("services")), root.join("pins.nix"));
+
+        // a file set by a config file is not searched for
+        let mut config = Config {
+            path: Some(tmp.path().join(CONFIG_FILE_NAME)),
+            ..Config::default()
+        };
+        config.locate_file_from(&nested);
+        assert_eq!(config.file, PathBuf::from("pins.nix"));
+    }
+}
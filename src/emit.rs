@@ -1,7 +1,34 @@
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Layout of the generated pins file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Style {
+    /// Shorthands like `nixpkgs.url = "...";` and single line `let` blocks where they fit
+    #[default]
+    Compact,
+    /// Every input as an attribute set and every helper on a line of its own
+    Expanded,
+}
+
+impl FromStr for Style {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact" => Ok(Style::Compact),
+            "expanded" => Ok(Style::Expanded),
+            _ => Err(format!("unknown style '{s}', expected compact or expanded")),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Context {
     depth: usize,
     indent_size: usize,
+    style: Style,
 }
 
 impl Default for Context {
@@ -9,11 +36,28 @@ impl Default for Context {
         Self {
             depth: 1,
             indent_size: 2,
+            style: Style::default(),
         }
     }
 }
 
 impl Context {
+    pub fn new(indent_size: usize) -> Self {
+        Self {
+            indent_size,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn style(&self) -> Style {
+        self.style
+    }
+
     fn indented(mut self) -> Context {
         self.depth += 1;
         self
@@ -37,6 +81,7 @@ pub enum Node {
 
     Attrlist(Vec<Node>),
     Attrset(Vec<Node>),
+    List(Vec<Node>),
     Attrpath(Vec<String>),
 
     Assign(Box<Node>, Box<Node>),
@@ -63,6 +108,7 @@ impl Node {
                         .join(", ")
                 )
             }
+            Node::Attrset(nodes) if nodes.is_empty() => "{ }".into(),
             Node::Attrset(nodes) => {
                 format!(
                     "{{\n{nodes}\n{indent}}}",
@@ -79,7 +125,10 @@ impl Node {
                 )
             }
             Node::Let(nodes) => {
-                let text = if nodes.len() == 1 && nodes.first().unwrap().emit(ctx).len() <= 60 {
+                let inline = ctx.style == Style::Compact
+                    && nodes.len() == 1
+                    && nodes.first().unwrap().emit(ctx).len() <= 60;
+                let text = if inline {
                     format!(" {} ", nodes.first().unwrap().emit(ctx))
                 } else {
                     format!(
@@ -98,13 +147,22 @@ impl Node {
 
                 format!("let{text}in")
             }
+            Node::List(nodes) if nodes.is_empty() => "[ ]".into(),
+            Node::List(nodes) => format!(
+                "[ {} ]",
+                nodes
+                    .iter()
+                    .map(|n| n.emit(ctx))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
             Node::Comment(text) => format!("# {text}"),
             Node::Attrpath(parts) => parts.join("."),
             Node::Assign(left, right) => format!("{} = {};", left.emit(ctx), right.emit(ctx)),
             Node::Call(left, right) => format!("{} {}", left.emit(ctx), right.emit(ctx)),
             Node::Ellipsis => "...".into(),
             Node::Raw(content) => content.trim().to_string(),
-            Node::String(content) => format!("\"{content}\""),
+            Node::String(content) => format!("\"{}\"", escape(content)),
         }
     }
 
@@ -128,3 +186,30 @@ impl Node {
         Node::String(value.to_string())
     }
 }
+
+/// Escape text for a double quoted nix string, so it can't end the string or interpolate
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+        .replace('\t', "\\t")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_strings() {
+        assert_eq!(
+            Node::string(r#"say "hi" \ ${builtins.abort "x"} $HOME"#).emit(Context::default()),
+            r#""say \"hi\" \\ \${builtins.abort \"x\"} $HOME""#
+        );
+        assert_eq!(
+            Node::string("two\nlines").emit(Context::default()),
+            r#""two\nlines""#
+        );
+    }
+}
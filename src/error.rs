@@ -0,0 +1,126 @@
+use std::{path::PathBuf, process::ExitStatus};
+
+/// Errors returned by nixpins operations
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// An url, config value or command output could not be parsed
+    #[error("{0}")]
+    Parse(String),
+    /// A pins, config or cache file could not be parsed
+    #[error("Unable to parse {path:?}")]
+    InvalidFile {
+        path: PathBuf,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// A line of the history file is not a valid entry
+    #[error("Invalid history entry on line {line} of {path:?}")]
+    InvalidHistory {
+        path: PathBuf,
+        line: usize,
+        #[source]
+        source: serde_json::Error,
+    },
+    /// Nix could not fetch a source
+    #[error("Unable to prefetch {flake_ref}: {stderr}")]
+    Prefetch { flake_ref: String, stderr: String },
+    /// Network access is disabled by --offline
+    #[error("Cannot {action} while offline, run without --offline")]
+    Offline { action: String },
+    /// A git repository is not in the expected state or git printed something unexpected
+    #[error("{0}")]
+    Git(String),
+    /// An external program exited unsuccessfully
+    #[error("{program} failed with {status}: {stderr}")]
+    Command {
+        program: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    /// A required environment variable is not set
+    #[error("{name} is not set")]
+    MissingEnv { name: String },
+    /// A path can't be used for the operation
+    #[error("{path:?} {reason}")]
+    InvalidPath { path: PathBuf, reason: String },
+    /// Pins or config could not be generated
+    #[error("{0}")]
+    Emit(String),
+    /// There is no pin of this name
+    #[error("No pin found of name {name}")]
+    PinNotFound { name: String },
+    /// The pin has no recorded source yet
+    #[error("Missing source for {name}")]
+    MissingSource { name: String },
+    /// Some other requested item does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// A pin of this name already exists
+    #[error("Pin already exists of name '{name}'")]
+    PinExists { name: String },
+    /// The pin is frozen and excluded from updates
+    #[error("{name} is {state}, run '{} unfreeze {name}' first", env!("CARGO_PKG_NAME"))]
+    Frozen { name: String, state: String },
+    /// The fetched source doesn't match the recorded hash
+    #[error("Hash mismatch for {name}: expected '{expected}', got '{actual}'")]
+    HashMismatch {
+        name: String,
+        expected: String,
+        actual: String,
+    },
+    /// The operation conflicts with the state of the pins
+    #[error("{0}")]
+    Conflict(String),
+    /// A file could not be read
+    #[error("Unable to read {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    /// A file could not be written
+    #[error("Unable to write {path:?}")]
+    Write {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Attach the path of a failed read to an io error
+    pub fn read(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Read { path, source }
+    }
+
+    /// Attach the path of a failed write to an io error
+    pub fn write(path: impl Into<PathBuf>) -> impl FnOnce(std::io::Error) -> Self {
+        let path = path.into();
+        move |source| Error::Write { path, source }
+    }
+}
+
+impl From<std::fmt::Error> for Error {
+    fn from(err: std::fmt::Error) -> Self {
+        Error::Emit(err.to_string())
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(err: toml::ser::Error) -> Self {
+        Error::Emit(err.to_string())
+    }
+}
+
+impl From<std::time::SystemTimeError> for Error {
+    fn from(err: std::time::SystemTimeError) -> Self {
+        Error::Io(std::io::Error::other(err))
+    }
+}
@@ -0,0 +1,44 @@
+use super::*;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct Bitbucket {
+    owner: String,
+    repo: String,
+    rev: String,
+    hash: String,
+}
+
+impl super::Source for Bitbucket {
+    fn function_name(&self) -> &'static str {
+        "fetchFromBitbucket"
+    }
+
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+        Self {
+            owner: url.owner.clone(),
+            repo: url.repo.clone(),
+            rev: pre.locked.rev,
+            hash: pre.hash,
+        }
+    }
+
+    fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    fn version(&self) -> &str {
+        &self.rev
+    }
+
+    fn node(&self) -> Node {
+        Node::call(
+            Node::Identifier(self.function_name().to_string()),
+            Node::Attrset(vec![
+                Node::assign(Node::ident("owner"), Node::string(&self.owner)),
+                Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+                Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+                Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+            ]),
+        )
+    }
+}
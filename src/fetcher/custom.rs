@@ -0,0 +1,502 @@
+use super::*;
+use crate::Error;
+use serde::de::DeserializeOwned;
+use std::{
+    any::TypeId,
+    cell::RefCell,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+
+/// Words of the nix language that can't be used as the name of a fetcher
+const KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+thread_local! {
+    /// Fetchers of the session reading a pins file or history on this thread, see [`Registry::scope`]
+    static READING: RefCell<Registry> = RefCell::default();
+}
+
+/// A fetcher that isn't built into nixpins, made available to a session with [`Registry::register`]
+///
+/// For example, pinning repositories of an internal gitea instance with a fetcher of its own:
+/// ```
+/// use nixpins::{emit::Node, fetcher::custom::{CustomSource, Registry}, prefetch::Prefetched, Session, Url};
+/// use serde::{Deserialize, Serialize};
+///
+/// #[derive(Clone, Debug, Deserialize, Serialize)]
+/// struct Corp {
+///     repo: String,
+///     rev: String,
+///     hash: String,
+/// }
+///
+/// impl CustomSource for Corp {
+///     fn matches(url: &Url) -> bool {
+///         url.host.as_deref() == Some("git.corp.example")
+///     }
+///
+///     fn args(&self) -> Node {
+///         Node::Attrset(vec![
+///             Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+///             Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+///             Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+///         ])
+///     }
+///
+///     fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+///         Self { repo: url.repo.clone(), rev: pre.locked.rev, hash: pre.hash }
+///     }
+///
+///     fn hash(&self) -> &str {
+///         &self.hash
+///     }
+///
+///     fn version(&self) -> &str {
+///         &self.rev
+///     }
+/// }
+///
+/// let mut fetchers = Registry::default();
+/// fetchers.register::<Corp>("fetchFromCorp")?;
+/// let session = Session::default().with_fetchers(fetchers);
+/// # Ok::<(), nixpins::Error>(())
+/// ```
+pub trait CustomSource:
+    Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    /// Nix expression the function is bound to in the generated pins file,
+    /// `None` to take it as an argument of the pins file like the fetchers of nixpkgs
+    const HELPER: Option<&'static str> = None;
+
+    /// Whether new pins of `url` are fetched with this fetcher
+    fn matches(url: &Url) -> bool;
+
+    /// Arguments the function is called with in the pins file
+    fn args(&self) -> Node;
+
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self;
+    fn hash(&self) -> &str;
+    fn version(&self) -> &str;
+
+    /// Prefetch the source of `url`, with `nix flake prefetch` by default
+    fn prefetch(session: &Session, url: &Url) -> Result<Prefetched> {
+        crate::prefetch::prefetch_url(session, url)
+    }
+
+    /// Prefetch the source of `url` again without reusing earlier results,
+    /// used to verify and realise pins, with `nix flake prefetch` by default
+    fn refetch(session: &Session, url: &Url) -> Result<Prefetched> {
+        crate::prefetch::refetch(session, url)
+    }
+}
+
+/// Fetchers available to a session in order of registration,
+/// see [`Session::with_fetchers`](crate::Session::with_fetchers)
+#[derive(Clone, Debug, Default)]
+pub struct Registry {
+    fetchers: Vec<Registration>,
+}
+
+impl Registry {
+    /// Emit sources of `T` as calls of the nix function `name`,
+    /// the first registered fetcher matching a url is used
+    ///
+    /// Registering the same fetcher again does nothing. A name that isn't a nix identifier,
+    /// or is taken by a builtin or another registered fetcher, is an error.
+    pub fn register<T: CustomSource>(&mut self, name: &'static str) -> Result<()> {
+        if !is_identifier(name) {
+            return Err(Error::Parse(format!(
+                "Unable to register {name:?}, it is not a nix identifier"
+            )));
+        }
+        if super::builtin_names().any(|builtin| builtin == name) {
+            return Err(Error::Conflict(format!(
+                "Unable to register {name}, it is the name of a builtin fetcher"
+            )));
+        }
+
+        match self.fetchers.iter().find(|r| r.name == name) {
+            Some(r) if r.type_id == TypeId::of::<T>() => {
+                log::debug!("{name} is already registered");
+                return Ok(());
+            }
+            Some(_) => {
+                return Err(Error::Conflict(format!(
+                    "Unable to register {name}, another fetcher of this name is registered"
+                )))
+            }
+            None => {}
+        }
+
+        self.fetchers.push(Registration {
+            type_id: TypeId::of::<T>(),
+            name,
+            matches: T::matches,
+            prefetch: T::prefetch,
+            refetch: T::refetch,
+            from_prefetched: |url, pre| Arc::new(T::from_prefetched(url, pre)),
+            deserialize: |args| Ok(Arc::new(serde_json::from_value::<T>(args)?)),
+        });
+        Ok(())
+    }
+
+    /// Run `read` with these fetchers available to deserialize [`Custom`] sources,
+    /// which have no other way to reach the session of the reader
+    pub(crate) fn scope<R>(&self, read: impl FnOnce() -> R) -> R {
+        let outer = READING.replace(self.clone());
+        let result = read();
+        READING.set(outer);
+        result
+    }
+
+    fn find(&self, url: &Url) -> Option<&Registration> {
+        self.fetchers.iter().find(|r| (r.matches)(url))
+    }
+
+    /// Bindings reading the calls of every registered fetcher back as a [`Fetcher::Custom`]
+    pub(super) fn stubs(&self) -> String {
+        self.fetchers
+            .iter()
+            .map(|r| {
+                format!(
+                    "  {name} = args: {{ Custom = {{ fetcher = \"{name}\"; inherit args; }}; }};\n",
+                    name = r.name
+                )
+            })
+            .collect()
+    }
+}
+
+/// Type erased constructors of a registered fetcher
+#[derive(Clone, Copy, Debug)]
+struct Registration {
+    type_id: TypeId,
+    name: &'static str,
+    matches: fn(&Url) -> bool,
+    prefetch: fn(&Session, &Url) -> Result<Prefetched>,
+    refetch: fn(&Session, &Url) -> Result<Prefetched>,
+    from_prefetched: fn(&Url, Prefetched) -> Arc<dyn Erased>,
+    deserialize: fn(serde_json::Value) -> Result<Arc<dyn Erased>>,
+}
+
+/// Whether `name` can be bound in a `let` block as is
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_'-".contains(c))
+        && !KEYWORDS.contains(&name)
+}
+
+/// Constructor of the session fetcher matching `url`, if any
+pub(super) fn constructor<'a>(
+    session: &Session,
+    url: &'a Url,
+) -> Option<impl FnOnce(Prefetched) -> Custom + 'a> {
+    let r = *session.fetchers().find(url)?;
+    Some(move |pre| Custom {
+        name: r.name,
+        source: (r.from_prefetched)(url, pre),
+    })
+}
+
+/// Prefetch `url` with the session fetcher matching it, if any
+pub(super) fn prefetch(session: &Session, url: &Url) -> Option<Result<Prefetched>> {
+    let r = session.fetchers().find(url)?;
+    Some((r.prefetch)(session, url))
+}
+
+/// Prefetch `url` again with the session fetcher matching it, if any
+pub(super) fn refetch(session: &Session, url: &Url) -> Option<Result<Prefetched>> {
+    let r = session.fetchers().find(url)?;
+    Some((r.refetch)(session, url))
+}
+
+/// Object safe part of [`CustomSource`]
+trait Erased: Debug + Send + Sync {
+    fn helper(&self) -> Option<&'static str>;
+    fn args(&self) -> Node;
+    fn hash(&self) -> &str;
+    fn version(&self) -> &str;
+    fn to_value(&self) -> serde_json::Result<serde_json::Value>;
+}
+
+impl<T: CustomSource> Erased for T {
+    fn helper(&self) -> Option<&'static str> {
+        T::HELPER
+    }
+
+    fn args(&self) -> Node {
+        CustomSource::args(self)
+    }
+
+    fn hash(&self) -> &str {
+        CustomSource::hash(self)
+    }
+
+    fn version(&self) -> &str {
+        CustomSource::version(self)
+    }
+
+    fn to_value(&self) -> serde_json::Result<serde_json::Value> {
+        serde_json::to_value(self)
+    }
+}
+
+/// Source of a fetcher registered with [`Registry::register`],
+/// serialized as the arguments of its function
+#[derive(Clone, Debug)]
+pub struct Custom {
+    name: &'static str,
+    source: Arc<dyn Erased>,
+}
+
+impl Custom {
+    /// Nix expression the function of the fetcher is bound to, see [`CustomSource::HELPER`]
+    pub fn helper(&self) -> Option<&'static str> {
+        self.source.helper()
+    }
+
+    pub fn function_name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn node(&self) -> Node {
+        Node::call(Node::ident(self.name), self.source.args())
+    }
+
+    pub fn hash(&self) -> &str {
+        self.source.hash()
+    }
+
+    pub fn version(&self) -> &str {
+        self.source.version()
+    }
+
+    fn args(&self) -> serde_json::Value {
+        self.source.to_value().unwrap_or_default()
+    }
+}
+
+impl PartialEq for Custom {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.args() == other.args()
+    }
+}
+
+impl Eq for Custom {}
+
+impl Hash for Custom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.args().to_string().hash(state);
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Raw {
+    fetcher: String,
+    args: serde_json::Value,
+}
+
+impl Serialize for Custom {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let args = self.source.to_value().map_err(serde::ser::Error::custom)?;
+        Raw {
+            fetcher: self.name.to_string(),
+            args,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Custom {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Raw::deserialize(deserializer)?;
+        let registration = READING
+            .with_borrow(|registry| {
+                registry
+                    .fetchers
+                    .iter()
+                    .find(|r| r.name == raw.fetcher)
+                    .copied()
+            })
+            .ok_or_else(|| {
+                serde::de::Error::custom(format!(
+                    "Unknown fetcher {}, it has to be registered with the session reading it",
+                    raw.fetcher
+                ))
+            })?;
+        Ok(Custom {
+            name: registration.name,
+            source: (registration.deserialize)(raw.args).map_err(serde::de::Error::custom)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emit::Context,
+        pins::Input,
+        testing::{prefetched, HASH, REV},
+        Pins,
+    };
+
+    /// `OTHER` makes it a different fetcher with the same implementation
+    #[derive(Clone, Debug, Deserialize, Serialize)]
+    struct TestForge<const OTHER: bool = false> {
+        repo: String,
+        rev: String,
+        hash: String,
+    }
+
+    impl<const OTHER: bool> CustomSource for TestForge<OTHER> {
+        // a `let … in` of its own must not end the helpers when reading the pins file back
+        const HELPER: Option<&'static str> =
+            Some("let plugin = import ./forge.nix; in args: plugin args");
+
+        fn matches(url: &Url) -> bool {
+            url.host.as_deref() == Some("custom.nixpins.invalid")
+        }
+
+        fn args(&self) -> Node {
+            Node::Attrset(vec![
+                Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+                Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+                Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+            ])
+        }
+
+        fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+            Self {
+                repo: url.repo.clone(),
+                rev: pre.locked.rev,
+                hash: pre.hash,
+            }
+        }
+
+        fn hash(&self) -> &str {
+            &self.hash
+        }
+
+        fn version(&self) -> &str {
+            &self.rev
+        }
+    }
+
+    fn session() -> Session {
+        let mut fetchers = Registry::default();
+        fetchers.register::<TestForge>("fetchFromForge").unwrap();
+        fetchers.register::<TestForge>("fetchFromForge").unwrap();
+        Session::default().with_fetchers(fetchers)
+    }
+
+    fn forge_url() -> Url {
+        Url::try_from("gitea:corp/tools?host=custom.nixpins.invalid".to_string()).unwrap()
+    }
+
+    #[test]
+    fn registered_fetcher() {
+        let session = session();
+        let pre = prefetched(REV);
+
+        let other = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        assert!(matches!(
+            Fetcher::new(&session, &other, pre.clone(), false),
+            Fetcher::Github(_)
+        ));
+        assert!(matches!(
+            Fetcher::new(&Session::default(), &forge_url(), pre.clone(), false),
+            Fetcher::Gitea(_)
+        ));
+
+        let fetcher = Fetcher::new(&session, &forge_url(), pre, false);
+        let Fetcher::Custom(custom) = &fetcher else {
+            panic!("expected a custom fetcher, got {fetcher:?}");
+        };
+        assert_eq!(custom.function_name(), "fetchFromForge");
+        assert_eq!(custom.helper(), TestForge::<false>::HELPER);
+        assert_eq!(fetcher.version(), REV);
+
+        let json = serde_json::to_value(&fetcher).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "Custom": {
+                    "fetcher": "fetchFromForge",
+                    "args": {
+                        "repo": "tools",
+                        "rev": REV,
+                        "hash": HASH
+                    }
+                }
+            })
+        );
+        let read = session
+            .fetchers()
+            .scope(|| serde_json::from_value::<Fetcher>(json.clone()));
+        assert_eq!(read.unwrap(), fetcher);
+        assert!(serde_json::from_value::<Fetcher>(json).is_err());
+        assert!(session.fetchers().stubs().contains(
+            "fetchFromForge = args: { Custom = { fetcher = \"fetchFromForge\"; inherit args; }; };"
+        ));
+    }
+
+    #[test]
+    fn invalid_names() {
+        let mut fetchers = Registry::default();
+        let register = |fetchers: &mut Registry, name| fetchers.register::<TestForge>(name);
+        for name in ["fetchGit", "enum"] {
+            assert!(matches!(
+                register(&mut fetchers, name),
+                Err(Error::Conflict(_))
+            ));
+        }
+        for name in ["", "in", "1fetch", "fetch from", "x = abort \"\"; y"] {
+            assert!(matches!(
+                register(&mut fetchers, name),
+                Err(Error::Parse(_))
+            ));
+        }
+        assert!(fetchers.fetchers.is_empty());
+
+        register(&mut fetchers, "fetch_from-forge'").unwrap();
+        assert!(matches!(
+            fetchers.register::<TestForge<true>>("fetch_from-forge'"),
+            Err(Error::Conflict(_))
+        ));
+        assert_eq!(fetchers.fetchers.len(), 1);
+    }
+
+    #[test]
+    fn read_back_from_file() {
+        let session = Arc::new(session());
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+
+        let fetcher = Fetcher::new(&session, &forge_url(), prefetched(REV), false);
+        assert!(matches!(fetcher, Fetcher::Custom(_)));
+
+        let mut pins = Pins::default().with_session(session.clone());
+        pins.inputs
+            .insert("tools".into(), Input::new(forge_url(), false));
+        pins.sources.insert("tools".into(), fetcher);
+        pins.write_to_file(&file, Context::default()).unwrap();
+
+        let content = std::fs::read_to_string(&file).unwrap();
+        assert!(content
+            .contains("fetchFromForge = let plugin = import ./forge.nix; in args: plugin args;"));
+        assert!(content.contains("fetchFromForge {"));
+
+        let read = Pins::read_from_file(&file, session).unwrap();
+        assert_eq!(read.inputs["tools"].url, pins.inputs["tools"].url);
+        assert_eq!(read.sources, pins.sources);
+        assert!(Pins::read_from_file(&file, Arc::default()).is_err());
+    }
+}
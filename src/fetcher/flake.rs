@@ -1,10 +1,75 @@
 use super::*;
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+/// Locked flake reference, evaluated purely through `builtins.getFlake`
+///
+/// `owner` and `repo` are set for forge types like `github`, `url` for `git` references
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(from = "Stored")]
 pub struct Flake {
-    pub url: crate::url::Url,
+    pub r#type: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    /// Domain of a self-hosted forge, `None` for the public instance
+    pub host: Option<String>,
+    pub url: Option<String>,
     pub rev: String,
-    pub hash: String,
+    #[serde(rename = "narHash")]
+    pub nar_hash: String,
+}
+
+/// Flake source as read from a pins file,
+/// also accepting the `{ url; rev; hash; }` form written by earlier versions
+#[derive(Deserialize)]
+struct Stored {
+    r#type: Option<String>,
+    owner: Option<String>,
+    repo: Option<String>,
+    host: Option<String>,
+    url: Option<String>,
+    rev: String,
+    #[serde(rename = "narHash", alias = "hash")]
+    nar_hash: String,
+}
+
+impl From<Stored> for Flake {
+    fn from(stored: Stored) -> Self {
+        let Stored {
+            r#type,
+            mut owner,
+            mut repo,
+            host,
+            mut url,
+            rev,
+            nar_hash,
+        } = stored;
+
+        // earlier versions only stored the flake url, like `github:nixos/nixpkgs`
+        let r#type = r#type.unwrap_or_else(|| {
+            let reference = url.take().unwrap_or_default();
+            match reference.split_once(':') {
+                Some((kind @ ("github" | "gitlab" | "sourcehut"), path)) => {
+                    let mut parts = path.splitn(3, '/');
+                    owner = parts.next().map(str::to_string);
+                    repo = parts.next().map(str::to_string);
+                    kind.to_string()
+                }
+                _ => {
+                    url = Some(reference.trim_start_matches("git+").to_string());
+                    "git".to_string()
+                }
+            }
+        });
+
+        Self {
+            r#type,
+            owner,
+            repo,
+            host,
+            url,
+            rev,
+            nar_hash,
+        }
+    }
 }
 
 impl Source for Flake {
@@ -12,16 +77,21 @@ impl Source for Flake {
         "fetchFlake"
     }
 
-    fn from_prefetched(pre: Prefetched) -> Self {
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
         Self {
-            url: crate::url::Url::try_from("github:juliamertz/nixpins".to_string()).unwrap(),
+            r#type: pre.locked.r#type,
+            owner: pre.locked.owner,
+            repo: pre.locked.repo,
+            // the locked url of `git` references already includes the domain
+            host: url.host.clone().filter(|_| pre.locked.url.is_none()),
+            url: pre.locked.url,
             rev: pre.locked.rev,
-            hash: pre.hash,
+            nar_hash: pre.locked.nar_hash.unwrap_or(pre.hash),
         }
     }
 
     fn hash(&self) -> &str {
-        &self.hash
+        &self.nar_hash
     }
 
     fn version(&self) -> &str {
@@ -29,16 +99,67 @@ impl Source for Flake {
     }
 
     fn node(&self) -> Node {
+        let mut fields = vec![Node::assign(
+            Node::ident("type"),
+            Node::string(&self.r#type),
+        )];
+        for (key, value) in [
+            ("owner", &self.owner),
+            ("repo", &self.repo),
+            ("host", &self.host),
+            ("url", &self.url),
+        ] {
+            if let Some(value) = value {
+                fields.push(Node::assign(Node::ident(key), Node::string(value)));
+            }
+        }
+        fields.push(Node::assign(Node::ident("rev"), Node::string(&self.rev)));
+        fields.push(Node::assign(
+            Node::ident("narHash"),
+            Node::string(&self.nar_hash),
+        ));
+
         Node::call(
             Node::Identifier(self.function_name().to_string()),
-            Node::Attrset(vec![
-                Node::assign(
-                    Node::ident("url"),
-                    Node::string(&self.url.fmt_clean().unwrap()),
-                ),
-                Node::assign(Node::ident("rev"), Node::string(&self.rev)),
-                Node::assign(Node::ident("hash"), Node::string(&self.hash)),
-            ]),
+            Node::Attrset(fields),
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emit::Context,
+        testing::{prefetched, HASH, REV},
+    };
+
+    #[test]
+    fn emit_locked() {
+        let url = Url::try_from("github:nixos/nixpkgs/nixos-unstable".to_string()).unwrap();
+        let flake = Flake::from_prefetched(&url, prefetched(REV));
+        assert_eq!(
+            flake.node().emit(Context::default()),
+            format!(
+                r#"fetchFlake {{
+  type = "github";
+  owner = "nixos";
+  repo = "nixpkgs";
+  rev = "{REV}";
+  narHash = "{HASH}";
+}}"#
+            )
+        );
+    }
+
+    #[test]
+    fn emit_host() {
+        let url = Url::try_from("github:o/r?host=ghe.example.com".to_string()).unwrap();
+        let flake = Flake::from_prefetched(&url, prefetched(REV));
+        assert_eq!(flake.host.as_deref(), Some("ghe.example.com"));
+        assert!(flake
+            .node()
+            .emit(Context::default())
+            .contains(r#"host = "ghe.example.com";"#));
+    }
+}
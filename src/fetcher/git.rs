@@ -0,0 +1,134 @@
+use super::*;
+
+/// Source fetched with `builtins.fetchGit`, used for ssh urls so private repositories
+/// are fetched with the ssh credentials of the evaluating user, and for remotes
+/// rewritten at emit time
+///
+/// Only the locked `rev` is emitted, `builtins.fetchGit` reads a `ref` like `v1.0`
+/// as the branch `refs/heads/v1.0`. Pins of a branch or tag other than the default branch
+/// fetch all refs instead, so their revision is found wherever it is.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(from = "Stored")]
+pub struct Git {
+    url: String,
+    #[serde(rename = "allRefs")]
+    all_refs: bool,
+    rev: String,
+    #[serde(rename = "narHash")]
+    nar_hash: String,
+}
+
+/// Git source as read from a pins file, also accepting the `ref` written by earlier versions
+#[derive(Deserialize)]
+struct Stored {
+    url: String,
+    r#ref: Option<String>,
+    #[serde(rename = "allRefs", default)]
+    all_refs: bool,
+    rev: String,
+    #[serde(rename = "narHash")]
+    nar_hash: String,
+}
+
+impl From<Stored> for Git {
+    fn from(stored: Stored) -> Self {
+        Self {
+            url: stored.url,
+            all_refs: stored.all_refs || stored.r#ref.is_some(),
+            rev: stored.rev,
+            nar_hash: stored.nar_hash,
+        }
+    }
+}
+
+impl Git {
+    /// Fetch from `url` instead of the remote of the pinned url, e.g. a rewritten remote
+    pub(super) fn with_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+}
+
+impl Source for Git {
+    fn function_name(&self) -> &'static str {
+        "fetchGit"
+    }
+
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+        Self {
+            url: url.git_remote(),
+            all_refs: url.tag.is_some(),
+            rev: pre.locked.rev,
+            nar_hash: pre.locked.nar_hash.unwrap_or(pre.hash),
+        }
+    }
+
+    fn hash(&self) -> &str {
+        &self.nar_hash
+    }
+
+    fn version(&self) -> &str {
+        &self.rev
+    }
+
+    fn node(&self) -> Node {
+        let mut fields = vec![Node::assign(Node::ident("url"), Node::string(&self.url))];
+        if self.all_refs {
+            fields.push(Node::assign(Node::ident("allRefs"), Node::ident("true")));
+        }
+        fields.extend([
+            Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+            Node::assign(Node::ident("narHash"), Node::string(&self.nar_hash)),
+        ]);
+
+        Node::call(
+            Node::Identifier(self.function_name().to_string()),
+            Node::Attrset(fields),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emit::Context,
+        testing::{prefetched, HASH, REV},
+    };
+
+    #[test]
+    fn emit_tagged() {
+        let url = Url::try_from("github:corp/tools/v1.0?ssh=true".to_string()).unwrap();
+        let git = Git::from_prefetched(&url, prefetched(REV));
+        assert_eq!(
+            git.node().emit(Context::default()),
+            format!(
+                r#"fetchGit {{
+  url = "ssh://git@github.com/corp/tools";
+  allRefs = true;
+  rev = "{REV}";
+  narHash = "{HASH}";
+}}"#
+            )
+        );
+
+        let url = Url::try_from("github:corp/tools?ssh=true".to_string()).unwrap();
+        let emitted = Git::from_prefetched(&url, prefetched(REV))
+            .node()
+            .emit(Context::default());
+        assert!(!emitted.contains("allRefs"));
+    }
+
+    #[test]
+    fn read_ref_of_earlier_versions() {
+        let git: Git = serde_json::from_value(serde_json::json!({
+            "url": "ssh://git@github.com/corp/tools",
+            "ref": "v1.0",
+            "rev": REV,
+            "narHash": HASH
+        }))
+        .unwrap();
+        assert!(git.all_refs);
+        assert!(!git.node().emit(Context::default()).contains("ref ="));
+    }
+}
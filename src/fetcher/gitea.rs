@@ -0,0 +1,48 @@
+use super::*;
+
+/// Gitea and Forgejo instances, including Codeberg
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct Gitea {
+    domain: String,
+    owner: String,
+    repo: String,
+    rev: String,
+    hash: String,
+}
+
+impl super::Source for Gitea {
+    fn function_name(&self) -> &'static str {
+        "fetchFromGitea"
+    }
+
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+        Self {
+            domain: url.domain().to_string(),
+            owner: url.owner.clone(),
+            repo: url.repo.clone(),
+            rev: pre.locked.rev,
+            hash: pre.hash,
+        }
+    }
+
+    fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    fn version(&self) -> &str {
+        &self.rev
+    }
+
+    fn node(&self) -> Node {
+        Node::call(
+            Node::Identifier(self.function_name().to_string()),
+            Node::Attrset(vec![
+                Node::assign(Node::ident("domain"), Node::string(&self.domain)),
+                Node::assign(Node::ident("owner"), Node::string(&self.owner)),
+                Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+                Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+                Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+            ]),
+        )
+    }
+}
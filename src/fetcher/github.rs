@@ -1,7 +1,9 @@
 use super::*;
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Github {
+    #[serde(rename = "githubBase")]
+    github_base: Option<String>,
     owner: String,
     repo: String,
     rev: String,
@@ -13,11 +15,11 @@ impl super::Source for Github {
         "fetchFromGitHub"
     }
 
-    fn from_prefetched(pre: Prefetched) -> Self {
-        assert_eq!(pre.original.r#type, "github");
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
         Self {
-            owner: pre.original.owner,
-            repo: pre.original.repo,
+            github_base: url.host.clone(),
+            owner: url.owner.clone(),
+            repo: url.repo.clone(),
             rev: pre.locked.rev,
             hash: pre.hash,
         }
@@ -32,14 +34,23 @@ impl super::Source for Github {
     }
 
     fn node(&self) -> Node {
+        let mut fields = vec![];
+        if let Some(github_base) = &self.github_base {
+            fields.push(Node::assign(
+                Node::ident("githubBase"),
+                Node::string(github_base),
+            ));
+        }
+        fields.extend([
+            Node::assign(Node::ident("owner"), Node::string(&self.owner)),
+            Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+            Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+            Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+        ]);
+
         Node::call(
             Node::Identifier(self.function_name().to_string()),
-            Node::Attrset(vec![
-                Node::assign(Node::ident("owner"), Node::string(&self.owner)),
-                Node::assign(Node::ident("repo"), Node::string(&self.repo)),
-                Node::assign(Node::ident("rev"), Node::string(&self.rev)),
-                Node::assign(Node::ident("hash"), Node::string(&self.hash)),
-            ]),
+            Node::Attrset(fields),
         )
     }
 }
@@ -1,7 +1,8 @@
 use super::*;
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub struct Gitlab {
+    domain: Option<String>,
     owner: String,
     repo: String,
     rev: String,
@@ -13,11 +14,11 @@ impl super::Source for Gitlab {
         "fetchFromGitLab"
     }
 
-    fn from_prefetched(pre: Prefetched) -> Self {
-        assert_eq!(pre.original.r#type, "gitlab");
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
         Self {
-            owner: pre.original.owner,
-            repo: pre.original.repo,
+            domain: url.host.clone(),
+            owner: url.owner.clone(),
+            repo: url.repo.clone(),
             rev: pre.locked.rev,
             hash: pre.hash,
         }
@@ -32,8 +33,20 @@ impl super::Source for Gitlab {
     }
 
     fn node(&self) -> Node {
-        todo!()
-        // let mut fields = vec![];
-        // Node::call(Node::Identifier(self.function_name().to_string()), Node::Attrset(fields))
+        let mut fields = vec![];
+        if let Some(domain) = &self.domain {
+            fields.push(Node::assign(Node::ident("domain"), Node::string(domain)));
+        }
+        fields.extend([
+            Node::assign(Node::ident("owner"), Node::string(&self.owner)),
+            Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+            Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+            Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+        ]);
+
+        Node::call(
+            Node::Identifier(self.function_name().to_string()),
+            Node::Attrset(fields),
+        )
     }
 }
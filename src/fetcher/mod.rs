@@ -1,59 +1,128 @@
+pub mod bitbucket;
+pub mod custom;
 pub mod flake;
+pub mod git;
+pub mod gitea;
 pub mod github;
 pub mod gitlab;
+pub mod sourcehut;
 
 use std::fmt::Display;
 
 use crate::emit::Node;
-pub(super) use crate::{prefetch::Prefetched, url::Url};
-pub(super) use anyhow::Result;
-pub(super) use serde::Deserialize;
+pub(super) use crate::Result;
+pub(super) use crate::{
+    prefetch::Prefetched,
+    session::Session,
+    url::{Provider, Url},
+};
+pub(super) use serde::{Deserialize, Serialize};
 
 pub trait Source {
     fn function_name(&self) -> &'static str;
     fn node(&self) -> Node;
-    fn from_prefetched(pre: Prefetched) -> Self;
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self
+    where
+        Self: Sized;
     fn hash(&self) -> &str;
     fn version(&self) -> &str;
 }
 
-#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
 pub enum Fetcher {
     Github(github::Github),
     Gitlab(gitlab::Gitlab),
+    Sourcehut(sourcehut::Sourcehut),
+    Gitea(gitea::Gitea),
+    Bitbucket(bitbucket::Bitbucket),
+    Git(git::Git),
     Flake(flake::Flake),
+    /// Fetcher registered with [`custom::Registry::register`]
+    Custom(custom::Custom),
 }
 
 impl Fetcher {
-    pub fn from_url(url: impl Display, flake: bool) -> Result<Self> {
+    pub fn from_url(session: &Session, url: impl Display, flake: bool) -> Result<Self> {
         let url = Url::try_from(url.to_string())?;
-        let pre = crate::prefetch::prefetch_url(&url)?;
+        let pre = prefetch(session, &url)?;
+        Ok(Fetcher::new(session, &url, pre, flake))
+    }
+
+    pub fn new(session: &Session, url: &Url, pre: Prefetched, flake: bool) -> Self {
         if flake {
-            Ok(Fetcher::Flake(flake::Flake {
-                url,
-                rev: pre.locked.rev,
-                hash: pre.hash,
-            }))
+            Fetcher::Flake(flake::Flake::from_prefetched(url, pre))
+        } else if let Some(new) = custom::constructor(session, url) {
+            Fetcher::Custom(new(pre))
+        } else if url.ssh || url.is_rewritten(session.rules()) {
+            // fetched with git like it was prefetched, see `Url::flake_ref`
+            let git = git::Git::from_prefetched(url, pre);
+            match url.emit_remote(session.rules()) {
+                Some(remote) => Fetcher::Git(git.with_url(remote)),
+                None => Fetcher::Git(git),
+            }
         } else {
-            Ok(Fetcher::from_prefetched(pre))
+            Fetcher::from_prefetched(url, pre)
         }
     }
 }
 
+/// Prefetch the source of `url` with the session fetcher matching it,
+/// or `nix flake prefetch` otherwise
+pub fn prefetch(session: &Session, url: &Url) -> Result<Prefetched> {
+    custom::prefetch(session, url).unwrap_or_else(|| crate::prefetch::prefetch_url(session, url))
+}
+
+/// Prefetch the source of `url` again without reusing earlier results,
+/// with the session fetcher matching it or `nix flake prefetch` otherwise
+pub fn refetch(session: &Session, url: &Url) -> Result<Prefetched> {
+    custom::refetch(session, url).unwrap_or_else(|| crate::prefetch::refetch(session, url))
+}
+
+const BUILTIN_STUBS: &str = include_str!("../stubs.nix");
+
+/// Nix bindings reading the function calls of a pins file back as [`Fetcher`] variants
+pub fn stubs(session: &Session) -> String {
+    format!(
+        "let\n{builtin}{custom}in\n",
+        builtin = BUILTIN_STUBS,
+        custom = session.fetchers().stubs()
+    )
+}
+
+/// Names bound by the builtin stubs, which session fetchers can't take
+fn builtin_names() -> impl Iterator<Item = &'static str> {
+    BUILTIN_STUBS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(" = ").map(|(name, _)| name))
+}
+
 impl Source for Fetcher {
     fn function_name(&self) -> &'static str {
         match self {
             Self::Github(v) => v.function_name(),
             Self::Gitlab(v) => v.function_name(),
+            Self::Sourcehut(v) => v.function_name(),
+            Self::Gitea(v) => v.function_name(),
+            Self::Bitbucket(v) => v.function_name(),
+            Self::Git(v) => v.function_name(),
             Self::Flake(v) => v.function_name(),
+            Self::Custom(v) => v.function_name(),
         }
     }
 
-    fn from_prefetched(pre: Prefetched) -> Self {
-        match pre.original.r#type.as_str() {
-            "github" => Fetcher::Github(github::Github::from_prefetched(pre)),
-            "gitlab" => Fetcher::Gitlab(gitlab::Gitlab::from_prefetched(pre)),
-            _ => unimplemented!(),
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+        match url.provider {
+            Provider::Github => Fetcher::Github(github::Github::from_prefetched(url, pre)),
+            Provider::Gitlab => Fetcher::Gitlab(gitlab::Gitlab::from_prefetched(url, pre)),
+            Provider::Sourcehut => {
+                Fetcher::Sourcehut(sourcehut::Sourcehut::from_prefetched(url, pre))
+            }
+            Provider::Gitea => Fetcher::Gitea(gitea::Gitea::from_prefetched(url, pre)),
+            Provider::Bitbucket => {
+                Fetcher::Bitbucket(bitbucket::Bitbucket::from_prefetched(url, pre))
+            }
         }
     }
 
@@ -61,7 +130,12 @@ impl Source for Fetcher {
         match self {
             Self::Github(field) => field.hash(),
             Self::Gitlab(field) => field.hash(),
+            Self::Sourcehut(field) => field.hash(),
+            Self::Gitea(field) => field.hash(),
+            Self::Bitbucket(field) => field.hash(),
+            Self::Git(field) => field.hash(),
             Self::Flake(field) => field.hash(),
+            Self::Custom(field) => field.hash(),
         }
     }
 
@@ -69,7 +143,12 @@ impl Source for Fetcher {
         match self {
             Self::Github(field) => field.version(),
             Self::Gitlab(field) => field.version(),
+            Self::Sourcehut(field) => field.version(),
+            Self::Gitea(field) => field.version(),
+            Self::Bitbucket(field) => field.version(),
+            Self::Git(field) => field.version(),
             Self::Flake(field) => field.version(),
+            Self::Custom(field) => field.version(),
         }
     }
 
@@ -77,7 +156,12 @@ impl Source for Fetcher {
         match self {
             Self::Github(field) => field.node(),
             Self::Gitlab(field) => field.node(),
+            Self::Sourcehut(field) => field.node(),
+            Self::Gitea(field) => field.node(),
+            Self::Bitbucket(field) => field.node(),
+            Self::Git(field) => field.node(),
             Self::Flake(field) => field.node(),
+            Self::Custom(field) => field.node(),
         }
     }
 }
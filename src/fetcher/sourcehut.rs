@@ -0,0 +1,81 @@
+use super::*;
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+pub struct Sourcehut {
+    domain: Option<String>,
+    owner: String,
+    repo: String,
+    rev: String,
+    hash: String,
+}
+
+impl super::Source for Sourcehut {
+    fn function_name(&self) -> &'static str {
+        "fetchFromSourcehut"
+    }
+
+    fn from_prefetched(url: &Url, pre: Prefetched) -> Self {
+        Self {
+            // fetchFromSourcehut clones from `git.${domain}`
+            domain: url
+                .host
+                .as_ref()
+                .map(|host| host.strip_prefix("git.").unwrap_or(host).to_string()),
+            owner: url.owner.clone(),
+            repo: url.repo.clone(),
+            rev: pre.locked.rev,
+            hash: pre.hash,
+        }
+    }
+
+    fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    fn version(&self) -> &str {
+        &self.rev
+    }
+
+    fn node(&self) -> Node {
+        let mut fields = vec![];
+        if let Some(domain) = &self.domain {
+            fields.push(Node::assign(Node::ident("domain"), Node::string(domain)));
+        }
+        fields.extend([
+            Node::assign(Node::ident("owner"), Node::string(&self.owner)),
+            Node::assign(Node::ident("repo"), Node::string(&self.repo)),
+            Node::assign(Node::ident("rev"), Node::string(&self.rev)),
+            Node::assign(Node::ident("hash"), Node::string(&self.hash)),
+        ]);
+
+        Node::call(
+            Node::Identifier(self.function_name().to_string()),
+            Node::Attrset(fields),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fetcher::Source, testing};
+
+    #[test]
+    fn domain_without_git_prefix() {
+        let pre = testing::prefetched(testing::REV);
+
+        let domain = |url: &str| {
+            let url = Url::try_from(url.to_string()).unwrap();
+            Sourcehut::from_prefetched(&url, pre.clone()).domain
+        };
+        assert_eq!(
+            domain("sourcehut:~corp/tools?host=git.example.org").as_deref(),
+            Some("example.org")
+        );
+        assert_eq!(
+            domain("sourcehut:~corp/tools?host=example.org").as_deref(),
+            Some("example.org")
+        );
+        assert_eq!(domain("sourcehut:~sircmpwn/hare"), None);
+    }
+}
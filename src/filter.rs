@@ -0,0 +1,109 @@
+/// Selects pins by a glob pattern over their names and the groups they are in
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Only include pins in one of these groups
+    pub groups: Vec<String>,
+    /// Leave out pins in any of these groups
+    pub exclude_groups: Vec<String>,
+    /// Name or glob pattern
+    pub pattern: Option<String>,
+}
+
+impl Filter {
+    pub fn with_pattern(mut self, pattern: Option<String>) -> Self {
+        self.pattern = pattern;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pattern.is_none() && self.groups.is_empty() && self.exclude_groups.is_empty()
+    }
+
+    /// The pin name when the filter addresses a single pin by its literal name
+    pub fn exact_name(&self) -> Option<&str> {
+        let pattern = self.pattern.as_deref()?;
+        let literal = !pattern.contains(['*', '?']);
+        (literal && self.groups.is_empty() && self.exclude_groups.is_empty()).then_some(pattern)
+    }
+
+    pub fn matches(&self, name: &str, groups: &[String]) -> bool {
+        let in_group = |group: &String| groups.contains(group);
+        self.pattern.as_deref().is_none_or(|p| glob(p, name))
+            && (self.groups.is_empty() || self.groups.iter().any(in_group))
+            && !self.exclude_groups.iter().any(in_group)
+    }
+}
+
+/// Match `text` against a pattern where `*` matches any sequence of characters
+/// and `?` matches a single character
+pub fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut p, mut t) = (0, 0);
+    // position of the last star and the text position it currently consumes up to
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("nvim-*", "nvim-treesitter"));
+        assert!(glob("nvim-*", "nvim-"));
+        assert!(!glob("nvim-*", "vim-surround"));
+        assert!(glob("*-plugin", "lsp-plugin"));
+        assert!(glob("n?xpkgs", "nixpkgs"));
+        assert!(glob("*a*b*", "xxaxxbxx"));
+        assert!(!glob("*a*b", "xxbxxa"));
+        assert!(glob("nixpkgs", "nixpkgs"));
+        assert!(!glob("nixpkgs", "nixpkgs-stable"));
+        assert!(glob("*", ""));
+    }
+
+    #[test]
+    fn selection() {
+        let groups = |names: &[&str]| names.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+        let plugins = groups(&["plugins"]);
+        let filter = Filter {
+            groups: vec!["plugins".into()],
+            exclude_groups: vec!["pinned".into()],
+            pattern: None,
+        };
+        assert!(filter.matches("telescope", &plugins));
+        assert!(!filter.matches("nixpkgs", &groups(&["channels"])));
+        assert!(!filter.matches("lualine", &groups(&["plugins", "pinned"])));
+
+        let filter = Filter::default().with_pattern(Some("nvim-*".into()));
+        assert!(filter.matches("nvim-cmp", &[]));
+        assert!(!filter.matches("telescope", &plugins));
+        assert_eq!(filter.exact_name(), None);
+
+        let filter = Filter::default().with_pattern(Some("nixpkgs".into()));
+        assert_eq!(filter.exact_name(), Some("nixpkgs"));
+    }
+}
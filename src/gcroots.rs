@@ -0,0 +1,163 @@
+use crate::{history::HISTORY_DIR, pins::Pins, Error, Result};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const GCROOTS_DIR: &str = "gcroots";
+
+/// Indirect garbage collector roots of the pinned sources, stored next to the pins file
+/// so `nix-collect-garbage` keeps them
+///
+/// Writing the pins file moves the root of a changed pin to its new source
+/// and removes the root of a removed pin, see [`Pins::write_to_file`].
+pub struct GcRoots {
+    dir: PathBuf,
+}
+
+impl GcRoots {
+    pub fn for_pins_file(file: &Path) -> Self {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        Self {
+            dir: dir.join(HISTORY_DIR).join(GCROOTS_DIR),
+        }
+    }
+
+    /// Register a root for the store path of every source and remove the roots of removed pins
+    pub fn register(&self, pins: &Pins) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        for name in pins.sources.keys() {
+            self.root(pins, name)?;
+        }
+
+        for name in self.prune(pins)? {
+            log::info!("Removed gc root of {name}");
+        }
+        Ok(())
+    }
+
+    /// Point an existing root of pin `name` at its current source,
+    /// or remove it when the pin has no source anymore
+    pub fn refresh(&self, pins: &Pins, name: &str) -> Result<()> {
+        let link = self.dir.join(name);
+        if std::fs::symlink_metadata(&link).is_err() {
+            return Ok(());
+        }
+
+        if pins.sources.contains_key(name) {
+            self.root(pins, name)
+        } else {
+            std::fs::remove_file(&link)?;
+            log::info!("Removed gc root of {name}");
+            Ok(())
+        }
+    }
+
+    fn root(&self, pins: &Pins, name: &str) -> Result<()> {
+        let pre = pins.realise(name)?;
+        let link = self.dir.join(name);
+        if std::fs::read_link(&link).ok().as_ref() == Some(&pre.store_path) {
+            log::debug!("{name} is already rooted at {:?}", pre.store_path);
+            return Ok(());
+        }
+
+        add_root(&link, &pre.store_path)?;
+        log::info!("Added gc root for {name} at {:?}", pre.store_path);
+        Ok(())
+    }
+
+    /// Move the root of pin `old` to `new`, registering it again under its new name
+    pub fn rename(&self, old: &str, new: &str) -> Result<()> {
+        let link = self.dir.join(old);
+        let Ok(store_path) = std::fs::read_link(&link) else {
+            return Ok(());
+        };
+
+        add_root(&self.dir.join(new), &store_path)?;
+        std::fs::remove_file(&link)?;
+        log::info!("Moved gc root of {old} to {new}");
+        Ok(())
+    }
+
+    /// Remove the roots of pins that no longer have a source, returns their names
+    pub fn prune(&self, pins: &Pins) -> Result<Vec<String>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut removed = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !pins.sources.contains_key(&name) {
+                std::fs::remove_file(entry.path())?;
+                removed.push(name);
+            }
+        }
+        removed.sort();
+        Ok(removed)
+    }
+}
+
+/// Point `link` at `store_path` and register it as an indirect root with the nix daemon
+fn add_root(link: &Path, store_path: &Path) -> Result<()> {
+    let output = Command::new("nix-store")
+        .arg("--realise")
+        .arg(store_path)
+        .arg("--add-root")
+        .arg(link)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Command {
+            program: format!("nix-store --add-root {link:?}"),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        emit::Context,
+        testing::{github, HASH, REV},
+    };
+
+    #[test]
+    fn prune_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let roots = GcRoots::for_pins_file(&tmp.path().join("pins.nix"));
+        assert!(roots.prune(&Pins::default()).unwrap().is_empty());
+
+        std::fs::create_dir_all(&roots.dir).unwrap();
+        for name in ["nixpkgs", "home-manager"] {
+            std::os::unix::fs::symlink("/nix/store/missing", roots.dir.join(name)).unwrap();
+        }
+
+        let removed = roots.prune(&Pins::default()).unwrap();
+        assert_eq!(removed, ["home-manager", "nixpkgs"]);
+        assert_eq!(std::fs::read_dir(&roots.dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn write_removes_root_of_removed_pin() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        let roots = GcRoots::for_pins_file(&file);
+        std::fs::create_dir_all(&roots.dir).unwrap();
+        std::os::unix::fs::symlink("/nix/store/missing", roots.dir.join("nixpkgs")).unwrap();
+
+        let mut pins = Pins::default();
+        pins.sources
+            .insert("nixpkgs".into(), github("nixos", "nixpkgs", REV, HASH));
+        // pins without a root are left alone
+        roots.refresh(&pins, "home-manager").unwrap();
+        assert!(!roots.dir.join("home-manager").exists());
+
+        pins.remove("nixpkgs");
+        pins.write_to_file(&file, Context::default()).unwrap();
+        assert_eq!(std::fs::read_dir(&roots.dir).unwrap().count(), 0);
+    }
+}
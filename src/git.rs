@@ -0,0 +1,510 @@
+use crate::{auth::Auth, session::Session, url, Error, Result};
+use fs4::fs_std::FileExt;
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Commit {
+    pub rev: String,
+    /// Commit time in seconds since the unix epoch
+    pub timestamp: u64,
+    pub author: String,
+    pub summary: String,
+}
+
+impl Commit {
+    pub fn short_rev(&self) -> &str {
+        short_rev(&self.rev)
+    }
+}
+
+pub fn short_rev(rev: &str) -> &str {
+    &rev[..rev.len().min(7)]
+}
+
+/// Bare clone of a remote repository without file contents, kept in the cache directory
+/// so repeated lookups only fetch new commits
+///
+/// The mirror is locked while open, so parallel updates don't fetch into it at the same time
+pub struct Mirror {
+    path: PathBuf,
+    _lock: File,
+}
+
+impl Mirror {
+    /// Open or create the mirror of `remote` in the cache directory of the session,
+    /// waiting for other handles on the same mirror to be dropped
+    pub fn open(session: &Session, remote: &str) -> Result<Self> {
+        Self::open_in(session, &session.cache_dir()?.join("git"), remote)
+    }
+
+    pub fn open_in(session: &Session, dir: &Path, remote: &str) -> Result<Self> {
+        let name = remote
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect::<String>();
+        let path = dir.join(&name);
+
+        std::fs::create_dir_all(dir)?;
+        let lock = File::create(dir.join(format!("{name}.lock")))?;
+        lock.lock_exclusive()?;
+
+        if path.exists() && session.is_offline() {
+            log::debug!("Using {path:?} without fetching {remote}");
+        } else if path.exists() {
+            log::debug!("Fetching {remote} into {path:?}");
+            git_remote(
+                session.auth(),
+                remote,
+                &[
+                    "--git-dir",
+                    path_str(&path)?,
+                    "fetch",
+                    "--quiet",
+                    "--prune",
+                    // only commits are inspected, file contents are never needed
+                    "--filter=blob:none",
+                    remote,
+                    "+refs/heads/*:refs/heads/*",
+                    "+refs/tags/*:refs/tags/*",
+                ],
+            )?;
+        } else {
+            session.ensure_online(&format!("clone {remote}"))?;
+            log::debug!("Cloning {remote} into {path:?}");
+            git_remote(
+                session.auth(),
+                remote,
+                &[
+                    "clone",
+                    "--bare",
+                    "--quiet",
+                    "--filter=blob:none",
+                    remote,
+                    path_str(&path)?,
+                ],
+            )?;
+        }
+
+        Ok(Self { path, _lock: lock })
+    }
+
+    /// Newest commit on the first-parent history of `reference` committed at or before `timestamp`
+    pub fn newest_before(&self, reference: &str, timestamp: i64) -> Result<Option<String>> {
+        let output = git(&[
+            "--git-dir",
+            path_str(&self.path)?,
+            "log",
+            "-1",
+            "--first-parent",
+            "--format=%H",
+            &format!(
+                "--before={}",
+                crate::history::format_timestamp(timestamp.max(0) as u64)
+            ),
+            reference,
+            "--",
+        ])?;
+
+        let rev = output.trim();
+        Ok((!rev.is_empty()).then(|| rev.to_string()))
+    }
+
+    /// Full hash of the commit an abbreviated `rev` refers to
+    pub fn expand_rev(&self, rev: &str) -> Result<String> {
+        let output = Command::new("git")
+            .args(["--git-dir", path_str(&self.path)?])
+            .args(["rev-parse", "--verify", "--quiet"])
+            .arg(format!("{rev}^{{commit}}"))
+            .output()?;
+
+        let full = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if !output.status.success() || full.is_empty() {
+            return Err(Error::Git(format!(
+                "{rev} is not a unique commit of {}",
+                self.path.display()
+            )));
+        }
+        Ok(full)
+    }
+
+    /// Whether `ancestor` is reachable from `rev`, a revision is its own ancestor
+    pub fn is_ancestor(&self, ancestor: &str, rev: &str) -> Result<bool> {
+        let status = Command::new("git")
+            .args(["--git-dir", path_str(&self.path)?])
+            .args(["merge-base", "--is-ancestor", ancestor, rev])
+            .status()?;
+
+        match status.code() {
+            Some(0) => Ok(true),
+            Some(1) => Ok(false),
+            _ => Err(Error::Command {
+                program: format!("git merge-base --is-ancestor {ancestor} {rev}"),
+                status,
+                stderr: String::new(),
+            }),
+        }
+    }
+
+    /// Commits reachable from `new` but not from `old`, newest first
+    pub fn log(&self, old: &str, new: &str) -> Result<Vec<Commit>> {
+        let output = git(&[
+            "--git-dir",
+            path_str(&self.path)?,
+            "log",
+            "--format=%H%x00%at%x00%an%x00%s",
+            &format!("{old}..{new}"),
+        ])?;
+
+        output
+            .lines()
+            .map(|line| {
+                let mut parts = line.splitn(4, '\0');
+                let mut next = || {
+                    parts
+                        .next()
+                        .ok_or_else(|| Error::Git("malformed git log output".into()))
+                };
+                Ok(Commit {
+                    rev: next()?.to_string(),
+                    timestamp: next()?
+                        .parse()
+                        .map_err(|_| Error::Git("malformed git log timestamp".into()))?,
+                    author: next()?.to_string(),
+                    summary: next()?.to_string(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Commit a remote branch or tag points to, peeling annotated tags
+///
+/// `reference` is `HEAD`, a full ref like `refs/tags/v1.0` or the name of a branch or tag.
+/// A name that is both resolves to the branch, like the `ref` of `builtins.fetchGit`.
+pub fn resolve_ref(session: &Session, remote: &str, reference: &str) -> Result<Option<String>> {
+    session.ensure_online(&format!("resolve {reference} of {remote}"))?;
+    let candidates = if reference == "HEAD" || reference.starts_with("refs/") {
+        vec![reference.to_string()]
+    } else {
+        vec![
+            format!("refs/heads/{reference}"),
+            format!("refs/tags/{reference}"),
+        ]
+    };
+
+    // peeled tags are only listed when asked for by name
+    let peeled = candidates
+        .iter()
+        .map(|name| format!("{name}^{{}}"))
+        .collect::<Vec<_>>();
+    let mut args = vec!["ls-remote", remote];
+    args.extend(candidates.iter().chain(&peeled).map(String::as_str));
+    let output = git_remote(session.auth(), remote, &args)?;
+
+    // patterns match any ref ending in them, so only exact names are taken
+    let mut revs = BTreeMap::new();
+    for line in output.lines() {
+        let Some((hash, name)) = line.split_once('\t') else {
+            continue;
+        };
+        // annotated tags are listed a second time, peeled to their commit
+        if name.ends_with("^{}") {
+            revs.insert(name.trim_end_matches("^{}"), hash);
+        } else {
+            revs.entry(name).or_insert(hash);
+        }
+    }
+
+    Ok(candidates
+        .iter()
+        .find_map(|name| revs.get(name.as_str()))
+        .map(|hash| hash.to_string()))
+}
+
+/// Work tree of the git repository containing the pins file
+pub struct Repository {
+    root: PathBuf,
+}
+
+impl Repository {
+    pub fn discover(path: &Path) -> Result<Self> {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let root = git(&["-C", path_str(dir)?, "rev-parse", "--show-toplevel"])
+            .map_err(|err| Error::Git(format!("{path:?} is not inside a git repository: {err}")))?;
+
+        Ok(Self {
+            root: PathBuf::from(root.trim()),
+        })
+    }
+
+    /// Whether any of `paths` has staged, unstaged or untracked changes,
+    /// paths that don't exist are skipped
+    pub fn is_dirty(&self, paths: &[&Path]) -> Result<bool> {
+        let paths = existing(paths)?;
+        if paths.is_empty() {
+            return Ok(false);
+        }
+
+        let mut args = vec!["-C", path_str(&self.root)?, "status", "--porcelain", "--"];
+        for path in &paths {
+            args.push(path_str(path)?);
+        }
+        Ok(!git(&args)?.trim().is_empty())
+    }
+
+    /// Commit the current contents of `paths`, leaving anything else in the index untouched,
+    /// paths that don't exist are skipped
+    pub fn commit_files(&self, paths: &[&Path], message: &str) -> Result<()> {
+        let paths = existing(paths)?;
+        let root = path_str(&self.root)?;
+        let mut add = vec!["-C", root, "add", "--"];
+        let mut commit = vec![
+            "-C", root, "commit", "--quiet", "-m", message, "--only", "--",
+        ];
+        for path in &paths {
+            add.push(path_str(path)?);
+            commit.push(path_str(path)?);
+        }
+
+        git(&add)?;
+        git(&commit)?;
+        Ok(())
+    }
+}
+
+fn existing(paths: &[&Path]) -> Result<Vec<PathBuf>> {
+    let mut existing = vec![];
+    for path in paths {
+        if path.exists() {
+            existing.push(path.canonicalize()?);
+        }
+    }
+    Ok(existing)
+}
+
+/// Cache directory of nixpins, following the XDG base directory specification
+pub fn cache_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME").ok_or_else(|| Error::MissingEnv {
+            name: "HOME".into(),
+        })?)
+        .join(".cache"),
+    };
+    Ok(base.join(env!("CARGO_PKG_NAME")))
+}
+
+fn path_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| Error::InvalidPath {
+        path: path.to_path_buf(),
+        reason: "is not valid unicode".into(),
+    })
+}
+
+fn git(args: &[&str]) -> Result<String> {
+    run(Command::new("git"), args)
+}
+
+/// Run git against `remote`, passing only the access token of its domain
+fn git_remote(auth: &Auth, remote: &str, args: &[&str]) -> Result<String> {
+    let mut command = Command::new("git");
+    if let Some((key, value)) = auth.git_config(url::remote_domain(remote)) {
+        // passed through the environment to keep the token out of the process list
+        let idx = std::env::var("GIT_CONFIG_COUNT")
+            .ok()
+            .and_then(|count| count.parse::<usize>().ok())
+            .unwrap_or(0);
+        command
+            .env(format!("GIT_CONFIG_KEY_{idx}"), key)
+            .env(format!("GIT_CONFIG_VALUE_{idx}"), value)
+            .env("GIT_CONFIG_COUNT", (idx + 1).to_string());
+    }
+    run(command, args)
+}
+
+fn run(mut command: Command, args: &[&str]) -> Result<String> {
+    let output = command.args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::Command {
+            program: format!("git {}", args.join(" ")),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::Repo;
+
+    #[test]
+    fn mirror_log() {
+        let session = Session::default();
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+
+        let first = repo.commit("first");
+        let cache = tmp.path().join("cache");
+        let mirror = Mirror::open_in(&session, &cache, &repo.remote()).unwrap();
+        assert!(mirror.log(&first, &first).unwrap().is_empty());
+        // opening blocks while another handle holds the lock
+        drop(mirror);
+
+        repo.commit("second");
+        let third = repo.commit("third");
+        let mirror = Mirror::open_in(&session, &cache, &repo.remote()).unwrap();
+        let summaries = mirror
+            .log(&first, &third)
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.summary)
+            .collect::<Vec<_>>();
+        assert_eq!(summaries, ["third", "second"]);
+    }
+
+    #[test]
+    fn expand_short_rev() {
+        let session = Session::default();
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+
+        let rev = repo.commit("first");
+        let mirror = Mirror::open_in(&session, &tmp.path().join("cache"), &repo.remote()).unwrap();
+        assert_eq!(mirror.expand_rev(short_rev(&rev)).unwrap(), rev);
+        assert!(mirror.expand_rev("does-not-exist").is_err());
+    }
+
+    #[test]
+    fn resolve_local_ref() {
+        let session = Session::default();
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let remote = repo.remote();
+
+        let first = repo.commit("first");
+        assert_eq!(
+            resolve_ref(&session, &remote, "HEAD").unwrap(),
+            Some(first.clone())
+        );
+        repo.git(&["branch", "stable"]);
+        repo.git(&["tag", "--annotate", "-m", "release", "v1.0"]);
+
+        let second = repo.commit("second");
+        assert_eq!(
+            resolve_ref(&session, &remote, "HEAD").unwrap(),
+            Some(second)
+        );
+        assert_eq!(
+            resolve_ref(&session, &remote, "stable").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            resolve_ref(&session, &remote, "v1.0").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(
+            resolve_ref(&session, &remote, "refs/tags/v1.0").unwrap(),
+            Some(first)
+        );
+        assert_eq!(resolve_ref(&session, &remote, "missing").unwrap(), None);
+    }
+
+    #[test]
+    fn resolve_ambiguous_ref() {
+        let session = Session::default();
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let remote = repo.remote();
+
+        let tagged = repo.commit("tagged");
+        repo.git(&["tag", "--annotate", "-m", "release", "release"]);
+        repo.git(&["tag", "--annotate", "-m", "release", "v1.0"]);
+        let nested = repo.commit("nested");
+        repo.git(&["branch", "x/v1.0"]);
+        repo.git(&["branch", "x/release"]);
+        let branch = repo.commit("branch");
+        repo.git(&["branch", "release"]);
+        repo.commit("head");
+
+        // the branch wins over a tag of the same name
+        assert_eq!(
+            resolve_ref(&session, &remote, "release").unwrap().as_ref(),
+            Some(&branch)
+        );
+        assert_eq!(
+            resolve_ref(&session, &remote, "refs/tags/release")
+                .unwrap()
+                .as_ref(),
+            Some(&tagged)
+        );
+        // branches only ending in the name are not matched
+        assert_eq!(
+            resolve_ref(&session, &remote, "v1.0").unwrap().as_ref(),
+            Some(&tagged)
+        );
+        assert_eq!(
+            resolve_ref(&session, &remote, "x/v1.0").unwrap().as_ref(),
+            Some(&nested)
+        );
+    }
+
+    #[test]
+    fn commit_only_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let work_tree = Repo::init(tmp.path());
+        let pins = tmp.path().join("pins.nix");
+        let history = tmp.path().join(".nixpins/history.jsonl");
+        let other = tmp.path().join("other.nix");
+        std::fs::write(&pins, "{ }").unwrap();
+        std::fs::write(&other, "{ }").unwrap();
+        work_tree.git(&["add", "other.nix"]);
+
+        let repo = Repository::discover(&pins).unwrap();
+        let tracked = [pins.as_path(), history.as_path()];
+        assert!(repo.is_dirty(&tracked).unwrap());
+        assert!(!repo.is_dirty(&[history.as_path()]).unwrap());
+
+        repo.commit_files(&tracked, "chore(pins): init").unwrap();
+        assert!(!repo.is_dirty(&tracked).unwrap());
+        assert!(repo.is_dirty(&[other.as_path()]).unwrap());
+
+        std::fs::write(&pins, "{ inputs = { }; }").unwrap();
+        std::fs::create_dir_all(history.parent().unwrap()).unwrap();
+        std::fs::write(&history, "{}\n").unwrap();
+        assert!(repo.is_dirty(&[history.as_path()]).unwrap());
+        repo.commit_files(&tracked, "chore(pins): update").unwrap();
+        assert!(!repo.is_dirty(&tracked).unwrap());
+    }
+
+    #[test]
+    fn newest_before() {
+        let session = Session::default();
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let revs = [
+            repo.commit_at("old", 1_000_000),
+            repo.commit_at("middle", 2_000_000),
+            repo.commit_at("new", 3_000_000),
+        ];
+
+        let mirror = Mirror::open_in(&session, &tmp.path().join("cache"), &repo.remote()).unwrap();
+        assert_eq!(
+            mirror.newest_before("HEAD", 2_500_000).unwrap().as_ref(),
+            Some(&revs[1])
+        );
+        assert_eq!(mirror.newest_before("HEAD", 500_000).unwrap(), None);
+        assert!(mirror.is_ancestor(&revs[0], &revs[2]).unwrap());
+        assert!(!mirror.is_ancestor(&revs[2], &revs[1]).unwrap());
+    }
+}
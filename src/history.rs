@@ -0,0 +1,261 @@
+use crate::{
+    fetcher::{custom::Registry, Fetcher, Source},
+    git, Error, Result,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const HISTORY_DIR: &str = ".nixpins";
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// A single change to the source of a pin
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Entry {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub name: String,
+    /// Source before the change, `None` when the pin was added
+    pub old: Option<Fetcher>,
+    /// Source after the change, `None` when the pin was removed
+    pub new: Option<Fetcher>,
+}
+
+impl Entry {
+    pub fn new(name: &str, old: Option<Fetcher>, new: Option<Fetcher>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            timestamp,
+            name: name.to_string(),
+            old,
+            new,
+        }
+    }
+
+    /// Short old and new revision, e.g. `0a1b2c3 -> 4d5e6f7`
+    pub fn revs(&self) -> String {
+        let short = |fetcher: &Option<Fetcher>, missing: &str| match fetcher {
+            Some(fetcher) => git::short_rev(fetcher.version()).to_string(),
+            None => missing.to_string(),
+        };
+
+        format!(
+            "{old} -> {new}",
+            old = short(&self.old, "(added)"),
+            new = short(&self.new, "(removed)"),
+        )
+    }
+
+    /// One line summary, e.g. `2025-03-01T12:00:00Z  0a1b2c3 -> 4d5e6f7`
+    pub fn summary(&self) -> String {
+        format!(
+            "{time}  {revs}",
+            time = format_timestamp(self.timestamp),
+            revs = self.revs()
+        )
+    }
+}
+
+/// Append-only log of source changes, stored next to the pins file
+pub struct History {
+    path: PathBuf,
+    /// Fetchers to read the sources of custom fetchers back with
+    fetchers: Registry,
+}
+
+impl History {
+    pub fn for_pins_file(file: &Path) -> Self {
+        let dir = file.parent().unwrap_or(Path::new("."));
+        Self {
+            path: dir.join(HISTORY_DIR).join(HISTORY_FILE),
+            fetchers: Registry::default(),
+        }
+    }
+
+    pub fn with_fetchers(mut self, fetchers: Registry) -> Self {
+        self.fetchers = fetchers;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn append(&self, entries: &[Entry]) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir).map_err(Error::write(dir))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(Error::write(&self.path))?;
+
+        for entry in entries {
+            writeln!(file, "{}", serde_json::to_string(entry)?)
+                .map_err(Error::write(&self.path))?;
+        }
+
+        Ok(())
+    }
+
+    /// Move the recorded changes of pin `old` to `new`
+    pub fn rename(&self, old: &str, new: &str) -> Result<()> {
+        let mut entries = self.read()?;
+        if !entries.iter().any(|entry| entry.name == old) {
+            return Ok(());
+        }
+
+        let mut buf = String::new();
+        for entry in &mut entries {
+            if entry.name == old {
+                entry.name = new.to_string();
+            }
+            buf += &serde_json::to_string(entry)?;
+            buf += "\n";
+        }
+
+        // replace the file at once so an interrupted rename keeps the old history
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, buf).map_err(Error::write(&tmp))?;
+        std::fs::rename(&tmp, &self.path).map_err(Error::write(&self.path))?;
+        Ok(())
+    }
+
+    pub fn read(&self) -> Result<Vec<Entry>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let content = std::fs::read_to_string(&self.path).map_err(Error::read(&self.path))?;
+        self.fetchers.scope(|| {
+            content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .enumerate()
+                .map(|(idx, line)| {
+                    serde_json::from_str(line).map_err(|source| Error::InvalidHistory {
+                        path: self.path.clone(),
+                        line: idx + 1,
+                        source,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    /// Changes recorded for a single pin, oldest first
+    pub fn entries_for(&self, name: &str) -> Result<Vec<Entry>> {
+        Ok(self
+            .read()?
+            .into_iter()
+            .filter(|entry| entry.name == name)
+            .collect())
+    }
+
+    /// Source to roll pin `name` back to, the one recorded by entry `to`
+    /// or the one before its latest change
+    pub fn rollback_source(&self, name: &str, to: Option<usize>) -> Result<Fetcher> {
+        let entries = self.entries_for(name)?;
+        match to {
+            Some(idx) => entries
+                .get(idx)
+                .ok_or_else(|| Error::NotFound(format!("No history entry {idx} for {name}")))?
+                .new
+                .clone()
+                .ok_or_else(|| {
+                    Error::Conflict(format!("{name} was removed in history entry {idx}"))
+                }),
+            None => entries
+                .last()
+                .ok_or_else(|| Error::NotFound(format!("No history recorded for {name}")))?
+                .old
+                .clone()
+                .ok_or_else(|| {
+                    Error::NotFound(format!(
+                        "No source recorded before the latest change of {name}"
+                    ))
+                }),
+        }
+    }
+}
+
+/// Format unix timestamp as an UTC RFC 3339 date
+pub fn format_timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rem = secs % 86400;
+
+    // civil from days, see https://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{github, HASH};
+
+    #[test]
+    fn timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_timestamp(951782400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_timestamp(1740830400), "2025-03-01T12:00:00Z");
+    }
+
+    #[test]
+    fn rollback_source() {
+        let tmp = tempfile::tempdir().unwrap();
+        let history = History::for_pins_file(&tmp.path().join("pins.nix"));
+        let source = |rev: &str| github("nixos", "nixpkgs", rev, HASH);
+        history
+            .append(&[
+                Entry::new("nixpkgs", None, Some(source("0000000000"))),
+                Entry::new(
+                    "nixpkgs",
+                    Some(source("0000000000")),
+                    Some(source("1111111111")),
+                ),
+                Entry::new("nixpkgs", Some(source("1111111111")), None),
+            ])
+            .unwrap();
+
+        let version = |to| {
+            history
+                .rollback_source("nixpkgs", to)
+                .map(|f| f.version().to_string())
+        };
+        assert_eq!(version(None).unwrap(), "1111111111");
+        assert_eq!(version(Some(0)).unwrap(), "0000000000");
+        assert!(matches!(version(Some(2)), Err(Error::Conflict(_))));
+        assert!(matches!(version(Some(3)), Err(Error::NotFound(_))));
+        assert!(matches!(
+            history.rollback_source("unstable", None),
+            Err(Error::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::Result;
+use nixpins::{
+    changelog::Changelog,
+    fetcher::Source,
+    git,
+    pins::{Candidate, Pins},
+};
+use std::{
+    collections::HashSet,
+    io::{BufRead, IsTerminal, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Let the user pick which of the resolved updates to apply
+///
+/// On a terminal a numbered list of updates is shown to choose from,
+/// otherwise the names of the pins to update are read from stdin
+pub fn select(pins: &Pins, candidates: Vec<Candidate>) -> Result<Vec<Candidate>> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        let mut names = HashSet::new();
+        for line in stdin.lock().lines() {
+            let line = line?;
+            names.extend(
+                line.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|name| !name.is_empty())
+                    .map(str::to_string),
+            );
+        }
+
+        for name in &names {
+            if !candidates.iter().any(|candidate| &candidate.name == name) {
+                log::warn!("No update available for {name}");
+            }
+        }
+
+        return Ok(candidates
+            .into_iter()
+            .filter(|candidate| names.contains(&candidate.name))
+            .collect());
+    }
+
+    print_table(pins, &candidates);
+
+    let selected = loop {
+        print!("Select updates to apply (e.g. '1 3-4', 'a' for all, empty for none): ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        stdin.lock().read_line(&mut line)?;
+        match parse_selection(&line, candidates.len()) {
+            Ok(selected) => break selected,
+            Err(err) => println!("{err}"),
+        }
+    };
+
+    Ok(candidates
+        .into_iter()
+        .enumerate()
+        .filter(|(idx, _)| selected.contains(idx))
+        .map(|(_, candidate)| candidate)
+        .collect())
+}
+
+fn print_table(pins: &Pins, candidates: &[Candidate]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default();
+
+    let mut rows = vec![[
+        "#".to_string(),
+        "NAME".into(),
+        "CURRENT".into(),
+        "LATEST".into(),
+        "AGE".into(),
+        "COMMITS".into(),
+    ]];
+    for (idx, candidate) in candidates.iter().enumerate() {
+        let current = pins.sources.get(&candidate.name);
+        rows.push([
+            (idx + 1).to_string(),
+            candidate.name.clone(),
+            current
+                .map(|source| git::short_rev(source.version()).to_string())
+                .unwrap_or("-".into()),
+            git::short_rev(candidate.fetcher.version()).to_string(),
+            format_age(now - candidate.last_modified),
+            commit_count(pins, candidate)
+                .map(|count| count.to_string())
+                .unwrap_or("?".into()),
+        ]);
+    }
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+}
+
+/// Amount of commits between the pinned and latest revision
+fn commit_count(pins: &Pins, candidate: &Candidate) -> Option<usize> {
+    let current = pins.sources.get(&candidate.name)?;
+    let input = pins.inputs.get(&candidate.name)?;
+    let changelog = Changelog::resolve(
+        pins.session(),
+        &candidate.name,
+        &input.url.fetch_remote(pins.session().rules()),
+        current.version(),
+        candidate.fetcher.version(),
+    );
+
+    match changelog {
+        Ok(changelog) => Some(changelog.commits.len()),
+        Err(err) => {
+            log::debug!("Unable to count commits of {}: {err:#}", candidate.name);
+            None
+        }
+    }
+}
+
+/// Parse a selection like `1 3-4` into zero based indices, `a` selects everything
+fn parse_selection(input: &str, len: usize) -> Result<Vec<usize>> {
+    let input = input.trim();
+    if input == "a" || input == "all" {
+        return Ok((0..len).collect());
+    }
+
+    let mut selected = vec![];
+    for part in input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|part| !part.is_empty())
+    {
+        let (start, end) = match part.split_once("-") {
+            Some((start, end)) => (start.parse::<usize>()?, end.parse::<usize>()?),
+            None => (part.parse()?, part.parse()?),
+        };
+        if start == 0 || end > len || start > end {
+            anyhow::bail!("'{part}' is not in range 1-{len}");
+        }
+        selected.extend(start - 1..end);
+    }
+
+    Ok(selected)
+}
+
+/// Format a duration in seconds as a short age like `3d`
+fn format_age(secs: i64) -> String {
+    match secs.max(0) {
+        secs if secs < 3600 => format!("{}m", secs / 60),
+        secs if secs < 86400 => format!("{}h", secs / 3600),
+        secs if secs < 86400 * 60 => format!("{}d", secs / 86400),
+        secs => format!("{}mo", secs / (86400 * 30)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selection() {
+        assert_eq!(parse_selection("\n", 3).unwrap(), Vec::<usize>::new());
+        assert_eq!(parse_selection("a\n", 3).unwrap(), [0, 1, 2]);
+        assert_eq!(parse_selection("1 3", 3).unwrap(), [0, 2]);
+        assert_eq!(parse_selection("2-3,1", 3).unwrap(), [1, 2, 0]);
+        assert!(parse_selection("4", 3).is_err());
+        assert!(parse_selection("0", 3).is_err());
+        assert!(parse_selection("x", 3).is_err());
+    }
+
+    #[test]
+    fn age() {
+        assert_eq!(format_age(59), "0m");
+        assert_eq!(format_age(7200), "2h");
+        assert_eq!(format_age(86400 * 3), "3d");
+        assert_eq!(format_age(86400 * 90), "3mo");
+    }
+}
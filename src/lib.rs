@@ -0,0 +1,41 @@
+//! Pin nix sources to exact revisions and emit them as a plain `pins.nix`
+//!
+//! The `nixpins` binary is a thin command line interface over this crate,
+//! tools can read, update and emit pins files the same way:
+//! ```no_run
+//! use nixpins::{config::UpdatePolicy, emit::Context, Pins, Session};
+//! use std::sync::Arc;
+//!
+//! let session = Arc::new(Session::default());
+//! let mut pins = Pins::read_from_file("pins.nix", session)?;
+//! pins.update("nixpkgs", &UpdatePolicy::default())?;
+//! pins.write_to_file("pins.nix", Context::default())?;
+//! # Ok::<(), nixpins::Error>(())
+//! ```
+
+pub mod auth;
+pub mod cache;
+pub mod changelog;
+pub mod config;
+pub mod emit;
+pub mod error;
+pub mod fetcher;
+pub mod filter;
+pub mod gcroots;
+pub mod git;
+pub mod history;
+pub mod mirror;
+pub mod pins;
+pub mod prefetch;
+pub mod rewrite;
+pub mod session;
+#[cfg(test)]
+mod testing;
+pub mod url;
+pub mod workspace;
+
+pub use error::{Error, Result};
+pub use fetcher::{Fetcher, Source};
+pub use pins::{Input, Pins};
+pub use session::Session;
+pub use url::Url;
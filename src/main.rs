@@ -1,26 +1,74 @@
-mod emit;
-mod fetcher;
-mod pins;
-mod prefetch;
-mod url;
+mod interactive;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use fetcher::Source;
-use pins::Pins;
-use std::path::PathBuf;
-use url::Url;
+use nixpins::{
+    auth, cache, changelog, config::Config, emit, filter::Filter, gcroots::GcRoots, git,
+    history::History, mirror, url, workspace, Pins, Session, Source, Url,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// Selects pins by the groups they are in, see [`Filter`]
+#[derive(Clone, Debug, Default, clap::Args)]
+struct FilterArgs {
+    /// Only include pins in this group, can be repeated
+    #[arg(long = "group", value_name = "GROUP")]
+    groups: Vec<String>,
+
+    /// Leave out pins in this group, can be repeated
+    #[arg(long = "exclude-group", value_name = "GROUP")]
+    exclude_groups: Vec<String>,
+}
+
+impl FilterArgs {
+    /// Combine with the name or glob pattern given as the positional argument of a command
+    fn with_pattern(self, pattern: Option<String>) -> Filter {
+        Filter {
+            groups: self.groups,
+            exclude_groups: self.exclude_groups,
+            pattern,
+        }
+    }
+}
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// Source to operate on
-    #[arg(default_value = "pins.nix")]
-    file: PathBuf,
+    /// Source to operate on, searched for in parent directories [default: pins.nix]
+    #[arg(env = "NIXPINS_FILE")]
+    file: Option<PathBuf>,
 
     #[arg(short, long)]
     dry: bool,
 
+    /// Print debug information
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// Map a custom domain to the provider it runs, e.g. ghe.corp.example=github
+    #[arg(long = "host", value_name = "DOMAIN=PROVIDER", value_parser = url::parse_host_mapping, global = true)]
+    hosts: Vec<(String, url::Provider)>,
+
+    /// Indent width of the generated pins file [default: 2]
+    #[arg(long, global = true)]
+    indent: Option<usize>,
+
+    /// Layout of the generated pins file, compact or expanded [default: compact]
+    #[arg(long, global = true)]
+    style: Option<emit::Style>,
+
+    /// Amount of sources to prefetch in parallel [default: 1]
+    #[arg(short, long, global = true)]
+    jobs: Option<usize>,
+
+    /// Never access the network, only use the pins file and the local cache
+    #[arg(long, global = true, env = "NIXPINS_OFFLINE")]
+    offline: bool,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -32,9 +80,12 @@ enum Command {
 
     /// Show pins and their versions
     Show {
-        /// Name of pin
+        /// Name or glob pattern of pins
         #[arg(short, long)]
         name: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
     },
 
     /// Add a new pin
@@ -48,6 +99,10 @@ enum Command {
         /// Name to addres this pin by
         #[arg(short, long)]
         name: Option<String>,
+
+        /// Group to label this pin with, can be repeated
+        #[arg(short, long = "group", value_name = "GROUP")]
+        groups: Vec<String>,
     },
 
     /// Remove a pin
@@ -58,37 +113,308 @@ enum Command {
 
     /// Update pin sources
     Update {
-        /// Name of pin to update
+        /// Name or glob pattern of pins to update
         name: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Lock the pin to a full or abbreviated commit hash, holding it there until the next explicit update
+        #[arg(long, requires = "name", conflicts_with = "tag")]
+        rev: Option<String>,
+
+        /// Lock the pin to a tag, holding it there until the next explicit update
+        #[arg(long, requires = "name")]
+        tag: Option<String>,
+
+        /// Only adopt revisions that are at least this many days old
+        #[arg(long, value_name = "DAYS")]
+        min_age: Option<u64>,
+
+        /// Resolve all updates first and choose which ones to apply
+        #[arg(short, long, conflicts_with_all = ["rev", "tag"])]
+        interactive: bool,
+
+        /// List the commits between the old and new revision of each updated pin
+        #[arg(long)]
+        changelog: bool,
+
+        /// Render the changelog as markdown
+        #[arg(long, requires = "changelog")]
+        markdown: bool,
+
+        /// Commit the updated pins file with a generated message
+        #[arg(long)]
+        commit: bool,
+
+        /// Create a separate commit for every updated pin, implies --commit
+        #[arg(long)]
+        commit_per_pin: bool,
+
+        /// Protect the pinned sources from garbage collection, see 'gcroots'
+        #[arg(long)]
+        add_gcroots: bool,
+
+        /// Update every pins file of the workspace instead of a single file
+        #[arg(long, conflicts_with_all = ["rev", "tag", "interactive", "commit", "commit_per_pin"])]
+        workspace: bool,
         // #[arg(short, long)]
         // recursive: bool,
     },
+
+    /// Rename a pin, keeping its source intact
+    Rename {
+        /// Current pin name
+        old: String,
+
+        /// New pin name
+        new: String,
+    },
+
+    /// Change the url, ref or flake setting of a pin
+    Set {
+        /// Pin name
+        name: String,
+
+        /// New input url
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Branch or tag to track, an empty value tracks the default branch
+        #[arg(long = "ref")]
+        reference: Option<String>,
+
+        /// Whether to fetch the pin as a flake
+        #[arg(long)]
+        flake: Option<bool>,
+
+        /// Only edit the input, leave the source as is
+        #[arg(long)]
+        no_resolve: bool,
+    },
+
+    /// List pins with newer revisions available
+    Outdated {
+        /// Name or glob pattern of pins
+        name: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Prefetch the pinned revisions again and check that their hashes still match
+    Verify {
+        /// Name or glob pattern of pins
+        name: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Compare against cached prefetch results instead of fetching,
+        /// pins without a cached result are reported as unverified
+        #[arg(long)]
+        local: bool,
+    },
+
+    /// Print the pins file as it would be written
+    Emit {
+        /// Fetch every source from its archive in a directory created by 'mirror'
+        #[arg(long, value_name = "DIR")]
+        mirror: Option<PathBuf>,
+    },
+
+    /// Print the store path of a pinned source, fetching it when missing
+    Path {
+        /// Pin name
+        name: String,
+    },
+
+    /// Ensure pinned sources are present in the store and print their paths
+    Realise {
+        /// Name or glob pattern of pins
+        name: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Realise every pinned source
+        #[arg(long, conflicts_with = "name")]
+        all: bool,
+
+        /// Print the store paths as a JSON object keyed by pin name
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Protect every pinned source from garbage collection with roots in .nixpins/gcroots
+    Gcroots,
+
+    /// Archive every pinned source into a directory for use without network access
+    Mirror {
+        /// Directory to store the archives and their index in
+        dir: PathBuf,
+    },
+
+    /// Print the pinned sources as JSON
+    Export,
+
+    /// Exclude a pin from updates
+    Freeze {
+        /// Pin name
+        name: String,
+
+        /// Why the pin is frozen, shown by 'show'
+        #[arg(short, long)]
+        reason: Option<String>,
+    },
+
+    /// Allow a frozen pin to be updated again
+    Unfreeze {
+        /// Pin name
+        name: String,
+    },
+
+    /// Never update a pin to a specific revision or tag
+    Ignore {
+        /// Pin name
+        name: String,
+
+        /// Revision (at least 7 characters) or tag name
+        version: String,
+
+        /// Stop ignoring the version
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Show recorded source changes of a pin
+    History {
+        /// Pin name
+        name: String,
+    },
+
+    /// List the commits of the latest recorded source change of a pin
+    Log {
+        /// Pin name
+        name: String,
+
+        /// Render the changelog as markdown
+        #[arg(long)]
+        markdown: bool,
+    },
+
+    /// Restore a previously recorded source of a pin without fetching
+    Rollback {
+        /// Pin name
+        name: String,
+
+        /// History entry to restore, defaults to the source before the latest change
+        #[arg(long)]
+        to: Option<usize>,
+    },
+
+    /// Inspect project configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Manage the cache of prefetched sources
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the effective configuration
+    Show,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove all cached prefetch results
+    Clear,
+
+    /// Print the amount of cached prefetch results
+    Stats,
 }
 
 pub fn main() -> Result<()> {
-    colog::init();
     let args = Cli::parse();
 
+    let mut logger = colog::default_builder();
+    if args.verbose {
+        logger.filter(None, log::LevelFilter::Debug);
+    }
+    logger.init();
+
+    let mut config = Config::discover()?;
+    match args.file {
+        Some(file) => config.file = file,
+        // init always creates a new file instead of finding an existing one
+        None if !matches!(args.command, Command::Init) => config.locate_file()?,
+        None => {}
+    }
+    if let Some(indent) = args.indent {
+        config.indent = indent;
+    }
+    if let Some(style) = args.style {
+        config.style = style;
+    }
+    if let Some(jobs) = args.jobs {
+        config.jobs = jobs;
+    }
+    config.hosts.extend(args.hosts);
+    let session = Arc::new(
+        Session::default()
+            .with_offline(args.offline)
+            .with_auth(auth::Auth::discover(&config.tokens, &config.hosts))
+            .with_rules(config.rewrite.clone())
+            .with_head_ttl(config.cache.head_ttl),
+    );
+
+    let file = &config.file;
+    log::debug!("Using pins file {file:?}");
+    let ctx = emit::Context::new(config.indent).with_style(config.style);
+
     match args.command {
         Command::Init => {
-            let pins = Pins::default();
-            pins.write_to_file(&args.file)?;
+            let mut pins = Pins::default();
+            pins.write_to_file(file, ctx)?;
         }
 
-        Command::Show { .. } => {
-            if !&args.file.exists() {
-                anyhow::bail!("Cannot find {:?} in current directory", &args.file);
+        Command::Show { name, filter } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
             }
 
-            let pins = Pins::read_from_file(&args.file)?;
+            let pins = read_pins(file, &session)?;
 
-            for (key, _pin) in pins.inputs.iter() {
-                match pins.sources.get(key) {
-                    Some(source) => log::info!(
-                        "{key}:\n  rev = '{rev}'\n  hash = '{hash}'",
-                        rev = source.version(),
-                        hash = source.hash()
-                    ),
+            for key in pins.select(&filter.with_pattern(name))? {
+                let pin = &pins.inputs[&key];
+                match pins.sources.get(&key) {
+                    Some(source) => {
+                        let mut text = format!(
+                            "{key}:\n  rev = '{rev}'\n  hash = '{hash}'",
+                            rev = source.version(),
+                            hash = source.hash()
+                        );
+                        if let Some(freeze) = &pin.freeze {
+                            text += &format!("\n  {}", freeze.describe());
+                        }
+                        if !pin.ignore.is_empty() {
+                            text += &format!("\n  ignored: {}", pin.ignore.join(", "));
+                        }
+                        if let Some(hold) = &pin.hold {
+                            text += &format!("\n  held at {hold}");
+                        }
+                        if !pin.groups.is_empty() {
+                            text += &format!("\n  groups: {}", pin.groups.join(", "));
+                        }
+                        log::info!("{text}")
+                    }
                     None => log::warn!(
                         "Missing source for {key} run '{package_name} update' to fix this",
                         package_name = env!("CARGO_PKG_NAME")
@@ -97,46 +423,545 @@ pub fn main() -> Result<()> {
             }
         }
 
-        Command::Add { url, name, flake } => {
-            if !&args.file.exists() {
-                anyhow::bail!("Cannot find {:?} in current directory", &args.file);
+        Command::Add {
+            url,
+            name,
+            flake,
+            groups,
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
             }
 
-            let url = Url::try_from(url)?;
-            let mut pins = Pins::read_from_file(&args.file)?;
-            pins.add(url, name, flake)?;
+            let url = Url::parse(&url, &config.hosts)?;
+            let mut pins = read_pins(file, &session)?;
+            pins.add(url, name, flake, groups)?;
             if !args.dry {
-                pins.write_to_file(&args.file)?;
+                pins.write_to_file(file, ctx)?;
             }
         }
 
         Command::Remove { name } => {
-            if !&args.file.exists() {
-                anyhow::bail!("Cannot find {:?} in current directory", &args.file);
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
             }
 
-            let mut pins = Pins::read_from_file(&args.file)?;
+            let mut pins = read_pins(file, &session)?;
             pins.remove(&name);
             if !args.dry {
-                pins.write_to_file(&args.file)?;
+                pins.write_to_file(file, ctx)?;
+                GcRoots::for_pins_file(file).prune(&pins)?;
+            }
+        }
+
+        Command::Update {
+            workspace: true,
+            name,
+            filter,
+            min_age,
+            changelog,
+            markdown,
+            add_gcroots,
+            ..
+        } => {
+            let mut policy = config.update.clone();
+            if min_age.is_some() {
+                policy.min_age = min_age;
+            }
+
+            let filter = filter.with_pattern(name);
+            let mut members = workspace::read(&config, &session)?;
+            for member in &mut members {
+                let names = member.pins.matching(&filter);
+                if names.is_empty() {
+                    continue;
+                }
+
+                log::info!("Updating {:?}...", member.path);
+                member.pins.update_all(&names, config.jobs, &policy)?;
+                if changelog {
+                    print_changelogs(&member.pins, markdown);
+                }
+            }
+
+            workspace::check_shared(&members, &config.workspace.shared)?;
+            if !args.dry {
+                for member in &mut members {
+                    member.pins.write_to_file(&member.path, ctx)?;
+                    if add_gcroots {
+                        GcRoots::for_pins_file(&member.path).register(&member.pins)?;
+                    }
+                }
+            }
+        }
+
+        Command::Update {
+            name,
+            filter,
+            rev,
+            tag,
+            min_age,
+            interactive,
+            changelog,
+            markdown,
+            commit,
+            commit_per_pin,
+            add_gcroots,
+            ..
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            // the history is committed together with the pins file
+            let history = History::for_pins_file(file);
+            let tracked = [file.as_path(), history.path()];
+            let repo = if commit || commit_per_pin {
+                let repo = git::Repository::discover(file)?;
+                if repo.is_dirty(&tracked)? {
+                    anyhow::bail!(
+                        "{file:?} or its history has uncommitted changes, commit or stash them first"
+                    );
+                }
+                Some(repo)
+            } else {
+                None
+            };
+
+            let mut policy = config.update.clone();
+            if min_age.is_some() {
+                policy.min_age = min_age;
+            }
+
+            let filter = filter.with_pattern(name);
+            let mut pins = read_pins(file, &session)?;
+            let names = pins.select(&filter)?;
+            match filter.exact_name() {
+                _ if interactive => {
+                    let candidates = pins
+                        .resolve_all(&names, config.jobs, &policy)?
+                        .into_iter()
+                        .filter(|candidate| pins.is_outdated(candidate))
+                        .collect::<Vec<_>>();
+
+                    if candidates.is_empty() {
+                        log::info!("Nothing to do.");
+                    } else {
+                        for candidate in interactive::select(&pins, candidates)? {
+                            pins.apply(candidate);
+                        }
+                    }
+                }
+                Some(name) => match (rev, tag) {
+                    (Some(rev), _) => pins.update_to_rev(name, &rev)?,
+                    (None, Some(tag)) => pins.update_to_tag(name, &tag)?,
+                    (None, None) => pins.update(name, &policy)?,
+                },
+                None if rev.is_some() || tag.is_some() => {
+                    anyhow::bail!("--rev and --tag require the exact name of a single pin")
+                }
+                None => pins.update_all(&names, config.jobs, &policy)?,
+            }
+
+            let changelogs = if changelog {
+                print_changelogs(&pins, markdown)
+            } else {
+                BTreeMap::new()
+            };
+
+            if !args.dry {
+                // writing moves the changes to the history
+                let changes = pins.changes().to_vec();
+                match (&repo, commit_per_pin) {
+                    (Some(repo), true) => pins.write_each_change(file, ctx, |entry| {
+                        let message =
+                            changelog::commit_message(std::slice::from_ref(entry), &changelogs);
+                        repo.commit_files(&tracked, &message)
+                    })?,
+                    _ => pins.write_to_file(file, ctx)?,
+                }
+                if add_gcroots {
+                    GcRoots::for_pins_file(file).register(&pins)?;
+                }
+
+                if let (Some(repo), false) = (&repo, commit_per_pin) {
+                    if changes.is_empty() {
+                        log::info!("Nothing to commit.");
+                    } else {
+                        let message = changelog::commit_message(&changes, &changelogs);
+                        repo.commit_files(&tracked, &message)?;
+                    }
+                }
+            }
+        }
+
+        Command::Rename { old, new } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let mut pins = read_pins(file, &session)?;
+            pins.rename(&old, &new)?;
+            if !args.dry {
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::Set {
+            name,
+            url,
+            reference,
+            flake,
+            no_resolve,
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let url = url.map(|url| Url::parse(&url, &config.hosts)).transpose()?;
+            let mut pins = read_pins(file, &session)?;
+            let changed = pins.set(&name, url, reference, flake)?;
+            let frozen = pins.inputs.get(&name).is_some_and(|i| i.freeze.is_some());
+
+            if !changed {
+                log::info!("Nothing to do.");
+            } else if no_resolve {
+                log::warn!(
+                    "Source of {name} was left unchanged, run 'update {name}' to resolve it"
+                );
+            } else if frozen {
+                log::warn!("{name} is frozen, its source was left unchanged");
+            } else {
+                pins.update(&name, &config.update)?;
+            }
+
+            if !args.dry {
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::Outdated { name, filter } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            let names = pins.select(&filter.with_pattern(name))?;
+            let candidates = pins
+                .resolve_all(&names, config.jobs, &config.update)?
+                .into_iter()
+                .filter(|candidate| pins.is_outdated(candidate))
+                .collect::<Vec<_>>();
+
+            if candidates.is_empty() {
+                log::info!("All pins are up to date.");
+            }
+            for candidate in candidates {
+                let current = pins
+                    .sources
+                    .get(&candidate.name)
+                    .map(|source| git::short_rev(source.version()))
+                    .unwrap_or("-");
+                println!(
+                    "{name}  {current} -> {latest}",
+                    name = candidate.name,
+                    latest = git::short_rev(candidate.fetcher.version())
+                );
+            }
+        }
+
+        Command::Verify {
+            name,
+            filter,
+            local,
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            let mut failed = vec![];
+            let mut uncached = vec![];
+            for name in pins.select(&filter.with_pattern(name))? {
+                let ok = if local {
+                    match pins.verify_local(&name)? {
+                        Some(ok) => ok,
+                        None => {
+                            uncached.push(name);
+                            continue;
+                        }
+                    }
+                } else {
+                    pins.verify(&name)?
+                };
+                if !ok {
+                    failed.push(name);
+                }
+            }
+
+            if !failed.is_empty() {
+                anyhow::bail!("Hash mismatch for {}", failed.join(", "));
+            }
+            if !uncached.is_empty() {
+                anyhow::bail!(
+                    "Unable to verify {} without a cached prefetch result, run without --local",
+                    uncached.join(", ")
+                );
+            }
+        }
+
+        Command::Emit { mirror } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            match mirror {
+                Some(dir) => {
+                    let index = mirror::read_index(&dir)?;
+                    print!("{}", pins.emit_mirrored(ctx, &dir, &index)?);
+                }
+                None => print!("{}", pins.emit(ctx)),
+            }
+        }
+
+        Command::Path { name } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            println!("{}", pins.realise(&name)?.store_path.display());
+        }
+
+        Command::Realise {
+            name,
+            filter,
+            all,
+            json,
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let filter = filter.with_pattern(name);
+            if !all && filter.is_empty() {
+                anyhow::bail!("Specify the pins to realise by name or group, or pass --all");
+            }
+
+            let pins = read_pins(file, &session)?;
+            let paths = pins.realise_all(&filter)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&paths)?);
+            } else {
+                for (name, path) in &paths {
+                    println!("{name}  {}", path.display());
+                }
+            }
+        }
+
+        Command::Gcroots => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            if !args.dry {
+                GcRoots::for_pins_file(file).register(&pins)?;
+            }
+        }
+
+        Command::Mirror { dir } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            let index = mirror::create(&pins, &dir)?;
+            log::info!("Mirrored {} sources into {dir:?}", index.len());
+        }
+
+        Command::Export => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            println!("{}", serde_json::to_string_pretty(&pins.export())?);
+        }
+
+        Command::Freeze { name, reason } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let mut pins = read_pins(file, &session)?;
+            pins.freeze(&name, reason)?;
+            if !args.dry {
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::Unfreeze { name } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let mut pins = read_pins(file, &session)?;
+            pins.unfreeze(&name)?;
+            if !args.dry {
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::Ignore {
+            name,
+            version,
+            remove,
+        } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let mut pins = read_pins(file, &session)?;
+            if remove {
+                pins.unignore(&name, &version)?;
+            } else {
+                pins.ignore(&name, version)?;
+            }
+            if !args.dry {
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::History { name } => {
+            let entries = history(file, &session).entries_for(&name)?;
+            if entries.is_empty() {
+                log::warn!("No history recorded for {name}");
+            }
+
+            for (idx, entry) in entries.iter().enumerate() {
+                println!("{idx}  {}", entry.summary());
             }
         }
 
-        Command::Update { name } => {
-            if !&args.file.exists() {
-                anyhow::bail!("Cannot find {:?} in current directory", &args.file);
+        Command::Log { name, markdown } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
+            }
+
+            let pins = read_pins(file, &session)?;
+            let entry = history(file, &session)
+                .entries_for(&name)?
+                .into_iter()
+                .rfind(|entry| entry.old.is_some() && entry.new.is_some())
+                .with_context(|| format!("No source change recorded for {name}"))?;
+
+            let log = pins
+                .changelog(&entry)
+                .expect("entry has old and new source")?;
+            if markdown {
+                print!("{}", log.markdown());
+            } else {
+                print!("{}", log.text());
             }
+        }
 
-            let mut pins = Pins::read_from_file(&args.file)?;
-            match name {
-                Some(ref name) => pins.update(name)?,
-                None => pins.update_all()?,
+        Command::Rollback { name, to } => {
+            if !file.exists() {
+                anyhow::bail!("Cannot find {file:?} in current or parent directories");
             }
+
+            let fetcher = history(file, &session).rollback_source(&name, to)?;
+
+            let mut pins = read_pins(file, &session)?;
+            pins.restore(&name, fetcher)?;
             if !args.dry {
-                pins.write_to_file(&args.file)?;
+                pins.write_to_file(file, ctx)?;
+            }
+        }
+
+        Command::Config {
+            command: ConfigCommand::Show,
+        } => {
+            if let Some(path) = &config.path {
+                println!("# loaded from {path:?}");
             }
+            print!("{}", config.to_toml()?);
+        }
+
+        Command::Cache {
+            command: CacheCommand::Clear,
+        } => {
+            if cache::clear(&session.cache_dir()?)? {
+                log::info!("Cleared the prefetch cache");
+            } else {
+                log::info!("Nothing to do.");
+            }
+        }
+
+        Command::Cache {
+            command: CacheCommand::Stats,
+        } => {
+            let dir = session.cache_dir()?;
+            let path = cache::Cache::path(&dir);
+            let stats = cache::Cache::load(&dir)?.stats(cache::now()?, config.cache.head_ttl);
+            let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+            println!("path: {path:?}");
+            println!("size: {size} bytes");
+            println!("revisions: {}", stats.revs);
+            println!(
+                "branches and tags: {} ({} fresh)",
+                stats.heads, stats.fresh_heads
+            );
         }
     }
 
     Ok(())
 }
+
+/// Read a pins file that fetches with the settings of this run
+fn read_pins(file: &Path, session: &Arc<Session>) -> Result<Pins> {
+    Ok(Pins::read_from_file(file, session.clone())?)
+}
+
+/// History of a pins file that reads sources with the fetchers of this run
+fn history(file: &Path, session: &Session) -> History {
+    History::for_pins_file(file).with_fetchers(session.fetchers().clone())
+}
+
+/// Print the commits of every source change, returning the changelogs by pin name
+fn print_changelogs(pins: &Pins, markdown: bool) -> BTreeMap<String, changelog::Changelog> {
+    let mut changelogs = BTreeMap::new();
+    for entry in pins.changes() {
+        match pins.changelog(entry) {
+            Some(Ok(log)) => {
+                if markdown {
+                    println!("{}", log.markdown());
+                } else {
+                    println!("{}", log.text());
+                }
+                changelogs.insert(entry.name.clone(), log);
+            }
+            Some(Err(err)) => log::warn!("Unable to list changes of {}: {err:#}", entry.name),
+            None => {}
+        }
+    }
+    changelogs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_from_env() {
+        std::env::set_var("NIXPINS_FILE", "nix/pins.nix");
+        let env = Cli::try_parse_from(["nixpins", "show"]).unwrap();
+        let arg = Cli::try_parse_from(["nixpins", "other.nix", "show"]).unwrap();
+        std::env::remove_var("NIXPINS_FILE");
+
+        assert_eq!(env.file, Some(PathBuf::from("nix/pins.nix")));
+        assert_eq!(arg.file, Some(PathBuf::from("other.nix")));
+    }
+}
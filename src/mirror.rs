@@ -0,0 +1,157 @@
+use crate::{fetcher::Source, git, pins::Pins, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path, process::Command};
+
+pub const INDEX_FILE: &str = "index.json";
+
+/// Source archived in a mirror directory
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Entry {
+    /// Upstream url of the pin
+    pub url: String,
+    pub rev: String,
+    /// Hash of the unpacked archive, identical to the hash of the pinned source
+    pub hash: String,
+    /// Archive relative to the mirror directory
+    pub file: String,
+}
+
+/// Archived sources by pin name, stored as `index.json` in the mirror directory
+pub type Index = BTreeMap<String, Entry>;
+
+/// Archive the locked revision of every source into `dir`,
+/// skipping sources that are already archived at the same revision
+pub fn create(pins: &Pins, dir: &Path) -> Result<Index> {
+    std::fs::create_dir_all(dir)?;
+    let mut index = read_index(dir).unwrap_or_default();
+
+    for (name, source) in &pins.sources {
+        let Some(input) = pins.inputs.get(name) else {
+            log::warn!("Skipping {name}: no input found for source");
+            continue;
+        };
+
+        let file = format!(
+            "{name}-{rev}.tar.gz",
+            rev = git::short_rev(source.version())
+        );
+        let entry = Entry {
+            url: input.url.fmt()?,
+            rev: source.version().to_string(),
+            hash: source.hash().to_string(),
+            file,
+        };
+        if index.get(name) == Some(&entry) && dir.join(&entry.file).exists() {
+            log::info!("{name} is already mirrored");
+            continue;
+        }
+
+        log::info!("Mirroring {name} at {}...", git::short_rev(&entry.rev));
+        let pre = pins.realise(name)?;
+        pack(&pre.store_path, &dir.join(&entry.file))?;
+        let file = entry.file.clone();
+        if let Some(prev) = index.insert(name.clone(), entry) {
+            if prev.file != file {
+                let _ = std::fs::remove_file(dir.join(prev.file));
+            }
+        }
+    }
+
+    index.retain(|name, _| pins.sources.contains_key(name));
+    std::fs::write(dir.join(INDEX_FILE), serde_json::to_string_pretty(&index)?)?;
+    Ok(index)
+}
+
+pub fn read_index(dir: &Path) -> Result<Index> {
+    let path = dir.join(INDEX_FILE);
+    let content = std::fs::read_to_string(&path).map_err(Error::read(&path))?;
+    serde_json::from_str(&content).map_err(|err| Error::InvalidFile {
+        path,
+        source: Box::new(err),
+    })
+}
+
+/// Archive a directory as a gzipped tarball with the directory as its single top-level entry,
+/// which `builtins.fetchTarball` strips when unpacking
+fn pack(path: &Path, out: &Path) -> Result<()> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(Error::InvalidPath {
+            path: path.to_path_buf(),
+            reason: "is not a store path".into(),
+        });
+    };
+
+    let tmp = out.with_extension("tmp");
+    let output = Command::new("tar")
+        .arg("-czf")
+        .arg(&tmp)
+        .arg("-C")
+        .arg(parent)
+        .arg(name)
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::Command {
+            program: format!("tar -czf {tmp:?}"),
+            status: output.status,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    std::fs::rename(&tmp, out)?;
+    Ok(())
+}
+
+/// Absolute location of the archive of an entry as a `file://` url
+pub fn file_url(dir: &Path, entry: &Entry) -> Result<String> {
+    let path = dir.canonicalize()?.join(&entry.file);
+    Ok(format!("file://{}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_single_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("abc-source");
+        std::fs::create_dir_all(source.join("lib")).unwrap();
+        std::fs::write(source.join("default.nix"), "{ }").unwrap();
+        std::fs::write(source.join("lib/util.nix"), "{ }").unwrap();
+
+        let out = tmp.path().join("source.tar.gz");
+        pack(&source, &out).unwrap();
+
+        let output = Command::new("tar").arg("-tzf").arg(&out).output().unwrap();
+        let mut entries = String::from_utf8(output.stdout)
+            .unwrap()
+            .lines()
+            .map(|line| line.trim_end_matches('/').to_string())
+            .collect::<Vec<_>>();
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                "abc-source",
+                "abc-source/default.nix",
+                "abc-source/lib",
+                "abc-source/lib/util.nix"
+            ]
+        );
+        assert_eq!(
+            file_url(
+                tmp.path(),
+                &Entry {
+                    url: "github:nixos/nixpkgs".into(),
+                    rev: "0".repeat(40),
+                    hash: "sha256-".into(),
+                    file: "source.tar.gz".into(),
+                }
+            )
+            .unwrap(),
+            format!(
+                "file://{}/source.tar.gz",
+                tmp.path().canonicalize().unwrap().display()
+            )
+        );
+    }
+}
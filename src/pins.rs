@@ -1,19 +1,144 @@
 use crate::{
-    fetcher::{Fetcher, Source},
-    url::Url,
+    changelog::Changelog,
+    config::UpdatePolicy,
+    emit::{Context, Node, Style},
+    fetcher::{self, Fetcher, Source},
+    filter::Filter,
+    gcroots::GcRoots,
+    git::{self, Mirror},
+    history::{self, History},
+    mirror,
+    prefetch::Prefetched,
+    session::Session,
+    url::{self, Url},
+    Error, Result,
 };
-use anyhow::Result;
 use regex::{Captures, Regex};
 use serde::Deserialize;
 use std::{
     collections::{BTreeMap, HashSet},
-    path::Path,
+    path::{Path, PathBuf},
+    sync::Arc,
 };
 
 #[derive(Debug, Deserialize)]
 pub struct Input {
-    url: Url,
-    flake: Option<bool>,
+    pub url: Url,
+    pub flake: Option<bool>,
+    /// Minimum age in days of a revision before it is adopted, overrides the global policy
+    #[serde(rename = "minAge")]
+    pub min_age: Option<u64>,
+    /// Excludes the pin from updates until unfrozen
+    pub freeze: Option<Freeze>,
+    /// Revisions or tags that are never adopted
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Revision or tag the pin was explicitly set to,
+    /// skipped by bulk updates until the next explicit update
+    pub hold: Option<String>,
+    /// Labels to select the pin by
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Freeze {
+    pub reason: Option<String>,
+}
+
+impl Freeze {
+    pub fn describe(&self) -> String {
+        match &self.reason {
+            Some(reason) => format!("frozen: {reason}"),
+            None => "frozen".into(),
+        }
+    }
+}
+
+impl Input {
+    pub fn new(url: Url, flake: bool) -> Self {
+        Self {
+            url,
+            flake: if flake { Some(true) } else { None },
+            min_age: None,
+            freeze: None,
+            ignore: vec![],
+            hold: None,
+            groups: vec![],
+        }
+    }
+
+    fn node(&self, name: &str, style: Style) -> Node {
+        let url = Node::String(self.url.fmt().unwrap());
+        if style == Style::Compact
+            && self.flake.is_none()
+            && self.min_age.is_none()
+            && self.freeze.is_none()
+            && self.ignore.is_empty()
+            && self.hold.is_none()
+            && self.groups.is_empty()
+        {
+            return Node::assign(Node::Attrpath(vec![name.to_owned(), "url".into()]), url);
+        }
+
+        let mut fields = vec![Node::assign(Node::ident("url"), url)];
+        if let Some(flake) = self.flake {
+            fields.push(Node::assign(
+                Node::ident("flake"),
+                Node::Identifier(format!("{flake:?}")),
+            ));
+        }
+        if let Some(min_age) = self.min_age {
+            fields.push(Node::assign(
+                Node::ident("minAge"),
+                Node::Identifier(min_age.to_string()),
+            ));
+        }
+        if let Some(freeze) = &self.freeze {
+            let mut attrs = vec![];
+            if let Some(reason) = &freeze.reason {
+                attrs.push(Node::assign(Node::ident("reason"), Node::string(reason)));
+            }
+            fields.push(Node::assign(Node::ident("freeze"), Node::Attrset(attrs)));
+        }
+        if !self.ignore.is_empty() {
+            fields.push(Node::assign(
+                Node::ident("ignore"),
+                Node::List(self.ignore.iter().map(|v| Node::string(v)).collect()),
+            ));
+        }
+        if let Some(hold) = &self.hold {
+            fields.push(Node::assign(Node::ident("hold"), Node::string(hold)));
+        }
+        if !self.groups.is_empty() {
+            fields.push(Node::assign(
+                Node::ident("groups"),
+                Node::List(self.groups.iter().map(|v| Node::string(v)).collect()),
+            ));
+        }
+
+        Node::assign(Node::Identifier(name.into()), Node::Attrset(fields))
+    }
+}
+
+/// Newly resolved source of a pin that has not been applied yet
+#[derive(Debug)]
+pub struct Candidate {
+    pub name: String,
+    pub fetcher: Fetcher,
+    /// Commit time of the resolved revision in seconds since the unix epoch
+    pub last_modified: i64,
+}
+
+/// Outcome of checking a prefetched head against the minimum age policy
+#[derive(Debug, PartialEq, Eq)]
+enum MinAge {
+    /// The head is old enough
+    Satisfied,
+    /// No eligible revision is newer than the pinned one
+    Hold,
+    /// Newest eligible revision
+    Older(String),
 }
 
 pub type Inputs = BTreeMap<String, Input>;
@@ -24,26 +149,62 @@ pub type Sources = BTreeMap<String, Fetcher>;
 pub struct Pins {
     pub inputs: Inputs,
     pub sources: Sources,
+
+    /// Source changes made since reading, recorded in the history on write
+    #[serde(skip)]
+    changes: Vec<history::Entry>,
+
+    /// Pins whose url or flake setting changed since reading, their sources are always prefetched
+    #[serde(skip)]
+    retargeted: HashSet<String>,
+
+    /// Renames since reading as old and new name, applied to the history and gc roots on write
+    #[serde(skip)]
+    renamed: Vec<(String, String)>,
+
+    /// Settings and state every fetch goes through
+    #[serde(skip)]
+    session: Arc<Session>,
 }
 
 impl Pins {
-    pub fn add(&mut self, url: Url, name: Option<String>, flake: bool) -> Result<()> {
+    /// Fetch with `session` from now on instead of a default one
+    pub fn with_session(mut self, session: Arc<Session>) -> Self {
+        self.session = session;
+        self
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Source changes made since reading that have not been written yet
+    pub fn changes(&self) -> &[history::Entry] {
+        &self.changes
+    }
+
+    pub fn add(
+        &mut self,
+        url: Url,
+        name: Option<String>,
+        flake: bool,
+        groups: Vec<String>,
+    ) -> Result<()> {
         let name = name.unwrap_or(url.repo.clone());
         if self.inputs.contains_key(&name) {
-            anyhow::bail!("Pin already exists of name '{name}'")
+            return Err(Error::PinExists { name });
         }
 
-        self.inputs.insert(
-            name.clone(),
-            Input {
-                url: url.clone(),
-                flake: if flake { Some(true) } else { None },
-            },
-        );
         log::info!("Adding new input {name} with url {:?}", &url);
-
-        let fetcher = Fetcher::from_url(url.fmt()?.as_str(), flake)?;
+        let fetcher = Fetcher::from_url(&self.session, url.fmt()?.as_str(), flake)?;
+        let input = Input {
+            groups,
+            ..Input::new(url, flake)
+        };
+        self.inputs.insert(name.clone(), input);
         log::info!("Added {name} at {version}", version = fetcher.version());
+        self.changes
+            .push(history::Entry::new(&name, None, Some(fetcher.clone())));
         self.sources.insert(name, fetcher);
         Ok(())
     }
@@ -52,28 +213,602 @@ impl Pins {
         if self.inputs.remove(name).is_some() {
             log::info!("Removed input for {name}");
         }
-        if self.sources.remove(name).is_some() {
+        if let Some(fetcher) = self.sources.remove(name) {
             log::info!("Removed source for {name}");
+            self.changes
+                .push(history::Entry::new(name, Some(fetcher), None));
+        }
+    }
+
+    /// Names of the pins matching the filter
+    pub fn matching(&self, filter: &Filter) -> Vec<String> {
+        self.inputs
+            .iter()
+            .filter(|(name, input)| filter.matches(name, &input.groups))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Names of the pins matching the filter, an error when a non-empty filter matches none
+    pub fn select(&self, filter: &Filter) -> Result<Vec<String>> {
+        let names = self.matching(filter);
+        if names.is_empty() && !filter.is_empty() {
+            return Err(Error::NotFound(
+                "No pins match the given name or groups".into(),
+            ));
+        }
+        Ok(names)
+    }
+
+    /// Commits between the old and new source of a recorded change,
+    /// `None` when the pin was added or removed
+    pub fn changelog(&self, entry: &history::Entry) -> Option<Result<Changelog>> {
+        let (Some(old), Some(new)) = (&entry.old, &entry.new) else {
+            return None;
+        };
+        let Some(input) = self.inputs.get(&entry.name) else {
+            return Some(Err(Error::PinNotFound {
+                name: entry.name.clone(),
+            }));
+        };
+
+        Some(Changelog::resolve(
+            &self.session,
+            &entry.name,
+            &input.url.fetch_remote(self.session.rules()),
+            old.version(),
+            new.version(),
+        ))
+    }
+
+    /// Replace the source of a pin with a previously recorded one, without fetching
+    pub fn restore(&mut self, name: &str, fetcher: Fetcher) -> Result<()> {
+        if !self.inputs.contains_key(name) {
+            return Err(Error::PinNotFound {
+                name: name.to_string(),
+            });
+        }
+
+        log::info!(
+            "Restored {name} to:\n  version = '{version}'\n  hash = '{hash}'",
+            version = fetcher.version(),
+            hash = fetcher.hash()
+        );
+        self.set_source(name, fetcher);
+        Ok(())
+    }
+
+    /// Move both the input and source of a pin to a new name, keeping the source intact
+    pub fn rename(&mut self, old: &str, new: &str) -> Result<()> {
+        if self.inputs.contains_key(new) {
+            return Err(Error::PinExists {
+                name: new.to_string(),
+            });
+        }
+        let Some(input) = self.inputs.remove(old) else {
+            return Err(Error::PinNotFound {
+                name: old.to_string(),
+            });
+        };
+
+        self.inputs.insert(new.to_string(), input);
+        if let Some(source) = self.sources.remove(old) {
+            self.sources.insert(new.to_string(), source);
+        }
+        for entry in self.changes.iter_mut().filter(|entry| entry.name == old) {
+            entry.name = new.to_string();
+        }
+        self.renamed.push((old.to_string(), new.to_string()));
+        log::info!("Renamed {old} to {new}");
+        Ok(())
+    }
+
+    /// Change the url, ref or flake setting of an input,
+    /// returns whether the pin now tracks a different target
+    pub fn set(
+        &mut self,
+        name: &str,
+        url: Option<Url>,
+        reference: Option<String>,
+        flake: Option<bool>,
+    ) -> Result<bool> {
+        let input = self.input_mut(name)?;
+        let prev_url = input.url.clone();
+        let prev_flake = input.flake.unwrap_or_default();
+
+        if let Some(url) = url {
+            input.url = url;
+        }
+        if let Some(reference) = reference {
+            input.url.tag = Some(reference).filter(|r| !r.is_empty());
+        }
+        if let Some(flake) = flake {
+            input.flake = flake.then_some(true);
+        }
+
+        let changed = input.url != prev_url || input.flake.unwrap_or_default() != prev_flake;
+        if changed {
+            log::info!("Set {name} to {url}", url = input.url.fmt()?);
+            self.retargeted.insert(name.to_string());
+        }
+        Ok(changed)
+    }
+
+    pub fn freeze(&mut self, name: &str, reason: Option<String>) -> Result<()> {
+        let input = self.input_mut(name)?;
+        input.freeze = Some(Freeze { reason });
+        log::info!("Froze {name}");
+        Ok(())
+    }
+
+    pub fn unfreeze(&mut self, name: &str) -> Result<()> {
+        if self.input_mut(name)?.freeze.take().is_some() {
+            log::info!("Unfroze {name}");
+        } else {
+            log::warn!("{name} is not frozen");
+        }
+        Ok(())
+    }
+
+    /// Never adopt `version`, a (short) revision or tag name, for the pin
+    pub fn ignore(&mut self, name: &str, version: String) -> Result<()> {
+        let input = self.input_mut(name)?;
+        if !input.ignore.contains(&version) {
+            log::info!("Ignoring {version} for {name}");
+            input.ignore.push(version);
+        }
+        Ok(())
+    }
+
+    pub fn unignore(&mut self, name: &str, version: &str) -> Result<()> {
+        let input = self.input_mut(name)?;
+        if input.ignore.iter().any(|v| v == version) {
+            input.ignore.retain(|v| v != version);
+            log::info!("No longer ignoring {version} for {name}");
+        } else {
+            log::warn!("{version} is not ignored for {name}");
+        }
+        Ok(())
+    }
+
+    fn input_mut(&mut self, name: &str) -> Result<&mut Input> {
+        match self.inputs.get_mut(name) {
+            Some(input) => Ok(input),
+            None => Err(Error::PinNotFound {
+                name: name.to_string(),
+            }),
         }
     }
 
-    pub fn update(&mut self, name: &str) -> Result<()> {
+    pub fn update(&mut self, name: &str, policy: &UpdatePolicy) -> Result<()> {
+        self.ensure_not_frozen(name)?;
+        if let Some(hold) = self.input_mut(name)?.hold.take() {
+            log::info!("Releasing hold of {name} at {hold}");
+        }
+
         log::info!("Updating {name}...");
+        match self.resolve(name, policy)? {
+            Some(candidate) => self.apply(candidate),
+            None => log::info!("Nothing to do."),
+        }
+        Ok(())
+    }
+
+    /// Lock a pin to an exact revision while it keeps tracking its branch,
+    /// holding it there until the next explicit update.
+    /// Abbreviated revisions are expanded against a mirror of the repository.
+    pub fn update_to_rev(&mut self, name: &str, rev: &str) -> Result<()> {
+        self.ensure_not_frozen(name)?;
+        let rev = if url::is_rev(rev) {
+            rev.to_string()
+        } else {
+            let url = self.input_mut(name)?.url.clone();
+            Mirror::open(&self.session, &url.fetch_remote(self.session.rules()))?.expand_rev(rev)?
+        };
+        self.update_to(name, &rev)
+    }
+
+    /// Lock a pin to a tag while it keeps tracking its branch,
+    /// holding it there until the next explicit update
+    pub fn update_to_tag(&mut self, name: &str, tag: &str) -> Result<()> {
+        self.update_to(name, tag)
+    }
+
+    fn update_to(&mut self, name: &str, version: &str) -> Result<()> {
+        self.ensure_not_frozen(name)?;
+        let session = self.session.clone();
+        let input = self.input_mut(name)?;
+        let url = Url {
+            tag: Some(version.to_string()),
+            ..input.url.clone()
+        };
+
+        log::info!("Updating {name} to {version}...");
+        let pre = fetcher::prefetch(&session, &url)?;
+        let last_modified = pre.locked.last_modified;
+        let fetcher = Fetcher::new(&session, &url, pre, input.flake.unwrap_or_default());
+        input.hold = Some(version.to_string());
+
+        self.apply(Candidate {
+            name: name.to_string(),
+            fetcher,
+            last_modified,
+        });
+        Ok(())
+    }
+
+    fn ensure_not_frozen(&self, name: &str) -> Result<()> {
+        if let Some(freeze) = self.inputs.get(name).and_then(|i| i.freeze.as_ref()) {
+            return Err(Error::Frozen {
+                name: name.to_string(),
+                state: freeze.describe(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Update the given pins, prefetching up to `jobs` sources in parallel
+    pub fn update_all(
+        &mut self,
+        names: &[String],
+        jobs: usize,
+        policy: &UpdatePolicy,
+    ) -> Result<()> {
+        for candidate in self.resolve_all(names, jobs, policy)? {
+            self.apply(candidate);
+        }
+
+        Ok(())
+    }
+
+    /// Prefetch the latest source of the given pins without applying them,
+    /// up to `jobs` in parallel
+    pub fn resolve_all(
+        &self,
+        names: &[String],
+        jobs: usize,
+        policy: &UpdatePolicy,
+    ) -> Result<Vec<Candidate>> {
+        let mut candidates = Vec::with_capacity(names.len());
+        for chunk in names.chunks(jobs.max(1)) {
+            let resolved = std::thread::scope(|scope| {
+                let handles = chunk
+                    .iter()
+                    .map(|name| {
+                        log::info!("Updating {name}...");
+                        scope.spawn(|| self.resolve(name, policy))
+                    })
+                    .collect::<Vec<_>>();
+
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("prefetch thread panicked"))
+                    .collect::<Vec<_>>()
+            });
+
+            for candidate in resolved {
+                candidates.extend(candidate?);
+            }
+        }
+
+        Ok(candidates)
+    }
+
+    /// Prefetch the latest source for an input that satisfies the update policy,
+    /// `None` when the policy holds the pin at its current revision
+    pub fn resolve(&self, name: &str, policy: &UpdatePolicy) -> Result<Option<Candidate>> {
         let input = match self.inputs.get(name) {
             Some(pin) => pin,
             None => {
-                anyhow::bail!("No pin found of name {name}");
+                return Err(Error::PinNotFound {
+                    name: name.to_string(),
+                });
             }
         };
 
-        let fetcher = Fetcher::from_url(
-            input.url.fmt()?,
-            self.inputs
-                .get(name)
-                .and_then(|i| i.flake)
-                .unwrap_or_default(),
-        )?;
+        if let Some(freeze) = &input.freeze {
+            log::info!("Skipping {name}: {}", freeze.describe());
+            return Ok(None);
+        }
+        if let Some(hold) = &input.hold {
+            log::info!("Skipping {name}: held at {hold}, update it by name to release");
+            return Ok(None);
+        }
+
+        if self.is_unchanged(name, input) {
+            log::info!("{name} is up to date");
+            return Ok(None);
+        }
 
+        let mut pre = fetcher::prefetch(&self.session, &input.url)?;
+        if let Some(days) = input.min_age.or(policy.min_age) {
+            match self.min_age_rev(name, input, &pre, days)? {
+                MinAge::Satisfied => {}
+                MinAge::Hold => return Ok(None),
+                MinAge::Older(rev) => {
+                    let url = Url {
+                        tag: Some(rev),
+                        ..input.url.clone()
+                    };
+                    pre = fetcher::prefetch(&self.session, &url)?;
+                }
+            }
+        }
+
+        if self.is_ignored(input, &pre.locked.rev)? {
+            log::info!(
+                "Skipping {name}: {rev} is ignored",
+                rev = git::short_rev(&pre.locked.rev)
+            );
+            return Ok(None);
+        }
+
+        let last_modified = pre.locked.last_modified;
+        Ok(Some(Candidate {
+            name: name.to_string(),
+            fetcher: Fetcher::new(
+                &self.session,
+                &input.url,
+                pre,
+                input.flake.unwrap_or_default(),
+            ),
+            last_modified,
+        }))
+    }
+
+    /// Whether the tracked ref still points at the pinned revision, checked with
+    /// `git ls-remote` so unchanged pins are not downloaded and hashed again
+    fn is_unchanged(&self, name: &str, input: &Input) -> bool {
+        let Some(current) = self.sources.get(name) else {
+            return false;
+        };
+        if self.retargeted.contains(name) {
+            return false;
+        }
+
+        let reference = match input.url.tag.as_deref() {
+            Some(tag) if url::is_rev(tag) => return tag == current.version(),
+            Some(tag) => tag,
+            None => "HEAD",
+        };
+        let remote = input.url.fetch_remote(self.session.rules());
+        match git::resolve_ref(&self.session, &remote, reference) {
+            Ok(Some(rev)) => rev == current.version(),
+            Ok(None) => false,
+            Err(err) => {
+                log::debug!(
+                    "Unable to resolve {reference} of {name}, prefetching instead: {err:#}"
+                );
+                false
+            }
+        }
+    }
+
+    /// Whether `rev` matches an ignored revision prefix or the commit of an ignored tag
+    fn is_ignored(&self, input: &Input, rev: &str) -> Result<bool> {
+        for version in &input.ignore {
+            let is_rev = version.len() >= 7 && version.chars().all(|c| c.is_ascii_hexdigit());
+            if is_rev {
+                if rev.starts_with(version.as_str()) {
+                    return Ok(true);
+                }
+                continue;
+            }
+
+            let tag = format!("refs/tags/{version}");
+            let remote = input.url.fetch_remote(self.session.rules());
+            if git::resolve_ref(&self.session, &remote, &tag)?.as_deref() == Some(rev) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Find the newest revision of the tracked ref that is at least `days` old
+    fn min_age_rev(
+        &self,
+        name: &str,
+        input: &Input,
+        head: &Prefetched,
+        days: u64,
+    ) -> Result<MinAge> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs() as i64;
+        // ages beyond the unix epoch leave no candidate instead of overflowing
+        let age = days
+            .checked_mul(86400)
+            .and_then(|secs| i64::try_from(secs).ok())
+            .unwrap_or(i64::MAX);
+        let cutoff = now.saturating_sub(age);
+
+        let tag = input.url.tag.as_deref();
+        if head.locked.last_modified <= cutoff || tag.is_some_and(url::is_rev) {
+            return Ok(MinAge::Satisfied);
+        }
+
+        let head_rev = git::short_rev(&head.locked.rev);
+        let mirror = Mirror::open(&self.session, &input.url.fetch_remote(self.session.rules()))?;
+        let Some(rev) = mirror.newest_before(tag.unwrap_or("HEAD"), cutoff)? else {
+            log::info!("Skipping {name}: no revision is at least {days} days old");
+            return Ok(MinAge::Hold);
+        };
+
+        if let Some(current) = self.sources.get(name) {
+            if mirror.is_ancestor(&rev, current.version())? {
+                log::info!(
+                    "Skipping {name}: latest revision {head_rev} is younger than {days} days \
+                     and no older revision is newer than the pinned {current}",
+                    current = git::short_rev(current.version())
+                );
+                return Ok(MinAge::Hold);
+            }
+        }
+
+        log::info!(
+            "{name}: latest revision {head_rev} is younger than {days} days, using {rev} instead",
+            rev = git::short_rev(&rev)
+        );
+        Ok(MinAge::Older(rev))
+    }
+
+    /// Prefetch the pinned revision again and compare its hash with the recorded one,
+    /// returns whether they match
+    pub fn verify(&self, name: &str) -> Result<bool> {
+        let Some(input) = self.inputs.get(name) else {
+            return Err(Error::PinNotFound {
+                name: name.to_string(),
+            });
+        };
+        let Some(source) = self.sources.get(name) else {
+            return Err(Error::MissingSource {
+                name: name.to_string(),
+            });
+        };
+
+        let url = Url {
+            tag: Some(source.version().to_string()),
+            ..input.url.clone()
+        };
+        let pre = fetcher::refetch(&self.session, &url)?;
+        let fetched = Fetcher::new(&self.session, &url, pre, input.flake.unwrap_or_default());
+        if fetched.hash() == source.hash() {
+            log::info!("{name}: ok");
+            return Ok(true);
+        }
+
+        log::error!(
+            "{name}: hash mismatch at {rev}\n  expected = '{expected}'\n  got = '{got}'",
+            rev = git::short_rev(source.version()),
+            expected = source.hash(),
+            got = fetched.hash()
+        );
+        Ok(false)
+    }
+
+    /// Fetch the pinned revision of a pin into the nix store,
+    /// an error when its hash differs from the recorded one
+    pub fn realise(&self, name: &str) -> Result<Prefetched> {
+        let Some(input) = self.inputs.get(name) else {
+            return Err(Error::PinNotFound {
+                name: name.to_string(),
+            });
+        };
+        let Some(source) = self.sources.get(name) else {
+            return Err(Error::MissingSource {
+                name: name.to_string(),
+            });
+        };
+
+        let url = Url {
+            tag: Some(source.version().to_string()),
+            ..input.url.clone()
+        };
+        let mut pre = fetcher::prefetch(&self.session, &url)?;
+        if !pre.store_path.exists() {
+            // cached results outlive their store paths after garbage collection
+            pre = fetcher::refetch(&self.session, &url)?;
+        }
+
+        let fetched = Fetcher::new(
+            &self.session,
+            &url,
+            pre.clone(),
+            input.flake.unwrap_or_default(),
+        );
+        if fetched.hash() != source.hash() {
+            return Err(Error::HashMismatch {
+                name: name.to_string(),
+                expected: source.hash().to_string(),
+                actual: fetched.hash().to_string(),
+            });
+        }
+        Ok(pre)
+    }
+
+    /// Fetch the pinned revisions of the pins matching the filter into the nix store,
+    /// returns their store paths by name, pins without a source are skipped
+    pub fn realise_all(&self, filter: &Filter) -> Result<BTreeMap<String, PathBuf>> {
+        let mut paths = BTreeMap::new();
+        for name in self.select(filter)? {
+            if !self.sources.contains_key(&name) {
+                log::warn!("Skipping {name}: missing source");
+                continue;
+            }
+            let path = self.realise(&name)?.store_path;
+            paths.insert(name, path);
+        }
+        Ok(paths)
+    }
+
+    /// Compare the recorded hash with the cached prefetch result of the pinned revision,
+    /// without network access, `None` when the revision is not cached
+    pub fn verify_local(&self, name: &str) -> Result<Option<bool>> {
+        let Some(input) = self.inputs.get(name) else {
+            return Err(Error::PinNotFound {
+                name: name.to_string(),
+            });
+        };
+        let Some(source) = self.sources.get(name) else {
+            return Err(Error::MissingSource {
+                name: name.to_string(),
+            });
+        };
+
+        let url = Url {
+            tag: Some(source.version().to_string()),
+            ..input.url.clone()
+        };
+        let Some(pre) = self.session.cached(&url)? else {
+            log::warn!(
+                "{name}: {rev} is not cached, unverified",
+                rev = git::short_rev(source.version())
+            );
+            return Ok(None);
+        };
+
+        let cached = Fetcher::new(&self.session, &url, pre, input.flake.unwrap_or_default());
+        if cached.hash() == source.hash() {
+            log::info!("{name}: ok");
+            return Ok(Some(true));
+        }
+
+        log::error!(
+            "{name}: hash mismatch at {rev}\n  expected = '{expected}'\n  cached = '{got}'",
+            rev = git::short_rev(source.version()),
+            expected = source.hash(),
+            got = cached.hash()
+        );
+        Ok(Some(false))
+    }
+
+    /// Pinned sources keyed by pin name, for consumption by other tools
+    pub fn export(&self) -> serde_json::Value {
+        self.sources
+            .iter()
+            .map(|(name, source)| {
+                let url = self.inputs.get(name).and_then(|input| input.url.fmt().ok());
+                let value = serde_json::json!({
+                    "url": url,
+                    "fetcher": source.function_name(),
+                    "rev": source.version(),
+                    "hash": source.hash(),
+                });
+                (name.clone(), value)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    /// Whether the candidate differs from the currently pinned source
+    pub fn is_outdated(&self, candidate: &Candidate) -> bool {
+        self.sources.get(&candidate.name) != Some(&candidate.fetcher)
+    }
+
+    pub fn apply(&mut self, candidate: Candidate) {
+        let Candidate { name, fetcher, .. } = candidate;
+        let name = name.as_str();
         if let Some(prev_fetcher) = self.sources.get(name) {
             if prev_fetcher == &fetcher {
                 log::info!("Nothing to do.")
@@ -86,67 +821,116 @@ impl Pins {
             }
         }
 
-        self.sources.insert(name.to_string(), fetcher);
-        Ok(())
+        self.set_source(name, fetcher);
     }
 
-    pub fn update_all(&mut self) -> Result<()> {
-        let keys = self.inputs.keys().cloned().collect::<Vec<_>>();
-        for input in keys {
-            self.update(&input)?;
+    fn set_source(&mut self, name: &str, fetcher: Fetcher) {
+        let prev = self.sources.insert(name.to_string(), fetcher.clone());
+        if prev.as_ref() != Some(&fetcher) {
+            self.changes
+                .push(history::Entry::new(name, prev, Some(fetcher)));
         }
-
-        Ok(())
     }
 }
 
 const NOTICE: &str = "this file was generated by nixpins (https://github.com/juliamertz/nixpins)";
 
 impl Pins {
-    pub fn emit(&self) -> String {
-        use crate::emit::Node;
+    pub fn emit(&self, ctx: Context) -> String {
+        let sources = self
+            .sources
+            .iter()
+            .map(|(name, value)| Node::assign(Node::Identifier(name.into()), value.node()))
+            .collect();
 
         let mut args: Vec<_> = self
             .sources
             .values()
-            .filter(|v| !matches!(v, Fetcher::Flake(_)))
+            .filter(|v| match v {
+                Fetcher::Flake(_) | Fetcher::Git(_) => false,
+                Fetcher::Custom(custom) => custom.helper().is_none(),
+                _ => true,
+            })
             .map(|v| Node::Identifier(v.function_name().into()))
             .collect::<HashSet<_>>()
             .into_iter()
             .collect();
         args.push(Node::Ellipsis);
 
-        let inputs: Vec<Node> = self
-            .inputs
-            .iter()
-            .map(|(name, value)| {
-                if value.flake.is_some() {
-                    Node::assign(
-                        Node::Identifier(name.into()),
-                        Node::Attrset(vec![
-                            Node::assign(
-                                Node::Identifier("url".into()),
-                                Node::String(value.url.fmt().unwrap()),
-                            ),
-                            Node::assign(
-                                Node::Identifier("flake".into()),
-                                Node::Identifier(format!("{:?}", value.flake.unwrap_or_default())),
-                            ),
-                        ]),
-                    )
-                } else {
-                    Node::assign(
-                        Node::Attrpath(vec![name.to_owned(), "url".into()]),
-                        Node::String(value.url.fmt().unwrap()),
-                    )
-                }
+        let mut helpers = vec![];
+        if self.inputs.iter().any(|(_, i)| i.flake.unwrap_or_default()) {
+            helpers.push(Node::Raw(
+                "fetchFlake = attrs: builtins.getFlake (builtins.flakeRefToString attrs);".into(),
+            ));
+        }
+        if self.sources.values().any(|v| matches!(v, Fetcher::Git(_))) {
+            helpers.push(Node::Raw("fetchGit = builtins.fetchGit;".into()));
+        }
+        let custom: BTreeMap<_, _> = self
+            .sources
+            .values()
+            .filter_map(|v| match v {
+                Fetcher::Custom(custom) => Some((custom.function_name(), custom.helper()?)),
+                _ => None,
             })
             .collect();
+        for (name, helper) in custom {
+            helpers.push(Node::Raw(format!("{name} = {helper};")));
+        }
 
-        let sources: Vec<Node> = self
-            .sources
+        self.emit_with(ctx, args, helpers, sources)
+    }
+
+    /// Emit pins with every source fetched from its archive in a mirror directory,
+    /// see [`mirror::create`]
+    pub fn emit_mirrored(&self, ctx: Context, dir: &Path, index: &mirror::Index) -> Result<String> {
+        let mut sources = vec![];
+        for (name, source) in &self.sources {
+            let entry = match index.get(name) {
+                Some(entry) if entry.rev == source.version() && entry.hash == source.hash() => {
+                    entry
+                }
+                _ => {
+                    return Err(Error::NotFound(format!(
+                        "{name} is not mirrored at its pinned revision in {dir:?}"
+                    )))
+                }
+            };
+            let url = mirror::file_url(dir, entry)?;
+            let flake = self
+                .inputs
+                .get(name)
+                .is_some_and(|input| input.flake.unwrap_or_default());
+
+            let node = if flake {
+                let flake_ref = format!("tarball+{url}?narHash={}", entry.hash);
+                Node::call(Node::ident("builtins.getFlake"), Node::string(&flake_ref))
+            } else {
+                Node::call(
+                    Node::ident("builtins.fetchTarball"),
+                    Node::Attrset(vec![
+                        Node::assign(Node::ident("url"), Node::string(&url)),
+                        Node::assign(Node::ident("sha256"), Node::string(&entry.hash)),
+                    ]),
+                )
+            };
+            sources.push(Node::assign(Node::Identifier(name.into()), node));
+        }
+
+        Ok(self.emit_with(ctx, vec![Node::Ellipsis], vec![], sources))
+    }
+
+    fn emit_with(
+        &self,
+        ctx: Context,
+        args: Vec<Node>,
+        helpers: Vec<Node>,
+        sources: Vec<Node>,
+    ) -> String {
+        let inputs: Vec<Node> = self
+            .inputs
             .iter()
-            .map(|(name, value)| Node::assign(Node::Identifier(name.into()), value.node()))
+            .map(|(name, value)| value.node(name, ctx.style()))
             .collect();
 
         let nodes = vec![
@@ -157,22 +941,17 @@ impl Pins {
             Node::assign(Node::ident("sources"), Node::Attrset(sources)),
         ];
 
-        let helpers = r#"
-          fetchFlake = attrs: builtins.getFlake "${attrs.url}/${attrs.rev}";
-        "#;
-
         let mut root = vec![
             Node::Comment(NOTICE.to_string()),
             Node::lambda(Node::Attrlist(args)),
         ];
 
-        if self.inputs.iter().any(|(_, i)| i.flake.unwrap_or_default()) {
-            root.push(Node::Let(vec![Node::Raw(helpers.into())]));
+        if !helpers.is_empty() {
+            root.push(Node::Let(helpers));
         }
         root.push(Node::Attrset(nodes));
 
         let mut buf = String::new();
-        let ctx = crate::emit::Context::default();
 
         for node in root {
             buf += &node.emit(ctx);
@@ -182,35 +961,586 @@ impl Pins {
         buf
     }
 
-    pub fn read_from_file(filepath: impl AsRef<Path>) -> Result<Pins> {
-        let content = std::fs::read_to_string(filepath.as_ref())?;
+    /// Read a pins file that fetches with `session`, which also provides its custom fetchers
+    pub fn read_from_file(filepath: impl AsRef<Path>, session: Arc<Session>) -> Result<Pins> {
+        let filepath = filepath.as_ref();
+        let content = std::fs::read_to_string(filepath).map_err(Error::read(filepath))?;
         let stripped = strip_arguments(&content);
-        let stubbed = format!("{}\n{}", include_str!("stubs.nix"), stripped);
-        let de = match tvix_serde::from_str(&stubbed) {
-            Ok(value) => value,
-            Err(err) => anyhow::bail!("Unable to deserialize pins, error: {err:#}"),
-        };
-        Ok(de)
+        let stubbed = format!("{}\n{}", fetcher::stubs(&session), stripped);
+        let pins: Pins = session
+            .fetchers()
+            .scope(|| tvix_serde::from_str(&stubbed))
+            // evaluation errors hold references to the evaluator and can't be sent as a source
+            .map_err(|err| Error::InvalidFile {
+                path: filepath.to_path_buf(),
+                source: format!("{err:#}").into(),
+            })?;
+        Ok(pins.with_session(session))
     }
 
-    pub fn write_to_file(&self, filepath: impl AsRef<Path>) -> Result<()> {
-        std::fs::write(filepath, self.emit())?;
+    /// Write pins to file and move the recorded source changes to its history,
+    /// carrying the history and gc roots of renamed pins over to their new name
+    /// and moving existing gc roots of changed pins to their new source
+    pub fn write_to_file(&mut self, filepath: impl AsRef<Path>, ctx: Context) -> Result<()> {
+        let filepath = filepath.as_ref();
+        std::fs::write(filepath, self.emit(ctx)).map_err(Error::write(filepath))?;
+
+        let history =
+            History::for_pins_file(filepath).with_fetchers(self.session.fetchers().clone());
+        let gcroots = GcRoots::for_pins_file(filepath);
+        for (old, new) in &self.renamed {
+            history.rename(old, new)?;
+            gcroots.rename(old, new)?;
+        }
+        self.renamed.clear();
+
+        history.append(&self.changes)?;
+        for entry in std::mem::take(&mut self.changes) {
+            gcroots.refresh(self, &entry.name)?;
+        }
         Ok(())
     }
+
+    /// Write pins to file once per recorded source change, calling `written` after each write.
+    /// Every write applies one more change on top of the file as it was read,
+    /// the last one writes the full result including changed inputs
+    pub fn write_each_change(
+        &mut self,
+        filepath: impl AsRef<Path>,
+        ctx: Context,
+        mut written: impl FnMut(&history::Entry) -> Result<()>,
+    ) -> Result<()> {
+        let filepath = filepath.as_ref();
+        let changes = std::mem::take(&mut self.changes);
+        let Some((last, earlier)) = changes.split_last() else {
+            return self.write_to_file(filepath, ctx);
+        };
+
+        let mut staged = Self::read_from_file(filepath, self.session.clone())?;
+        for entry in earlier {
+            if let Some(new) = &entry.new {
+                staged.sources.insert(entry.name.clone(), new.clone());
+            }
+            staged.changes.push(entry.clone());
+            staged.write_to_file(filepath, ctx)?;
+            written(entry)?;
+        }
+
+        self.changes.push(last.clone());
+        self.write_to_file(filepath, ctx)?;
+        written(last)
+    }
 }
 
 /// Strip anything from pins.nix expression that is not the main data structure
 fn strip_arguments(code: &str) -> String {
-    let mut buf = code.to_string();
+    // only the header is stripped, strings further down may contain anything;
+    // helpers may hold a `let … in` of their own, so the block ends where the inputs begin
+    let header =
+        Regex::new(r"^(\s*#[^\n]*\n)*\s*(\{[^}]*\}:)?\s*(?s:let\b.*?\bin\s*(\{\s*inputs\s*=))?")
+            .unwrap();
+    header
+        .replace(code, |caps: &Captures| {
+            caps.get(3).map_or("", |body| body.as_str()).to_string()
+        })
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{github, prefetched, Repo, HASH, REV};
 
-    let re = Regex::new(r"#.*\n").unwrap();
-    buf = re.replace(&buf, |_: &Captures| "").to_string();
+    #[test]
+    fn emit_inputs() {
+        let mut pins = Pins::default();
+        let url = Url::try_from("github:nixos/nixpkgs/nixos-unstable".to_string()).unwrap();
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+        let url = Url::try_from("github:nix-community/home-manager".to_string()).unwrap();
+        pins.inputs
+            .insert("home-manager".into(), Input::new(url, false));
 
-    let re = Regex::new(r"\{.*\}:").unwrap();
-    buf = re.replace(&buf, |_: &Captures| "").to_string();
+        pins.freeze("home-manager", Some("waiting for #123".into()))
+            .unwrap();
+        pins.ignore("home-manager", "v25.05".into()).unwrap();
 
-    let re = Regex::new(r"let(.*\n)*in").unwrap();
-    buf = re.replace(&buf, |_: &Captures| "").to_string();
+        let emitted = pins.emit(Context::default());
+        assert!(emitted.contains(r#"nixpkgs.url = "github:nixos/nixpkgs/nixos-unstable";"#));
+        assert!(emitted.contains(
+            r#"    home-manager = {
+      url = "github:nix-community/home-manager";
+      freeze = {
+        reason = "waiting for #123";
+      };
+      ignore = [ "v25.05" ];
+    };"#
+        ));
+    }
+
+    #[test]
+    fn emit_expanded() {
+        let mut pins = Pins::default();
+        let url = Url::try_from("github:nixos/nixpkgs/nixos-unstable".to_string()).unwrap();
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+
+        let compact = pins.emit(Context::default());
+        assert!(compact.contains(r#"nixpkgs.url = "github:nixos/nixpkgs/nixos-unstable";"#));
+
+        let expanded = pins.emit(Context::default().with_style(Style::Expanded));
+        assert!(expanded.contains(
+            r#"    nixpkgs = {
+      url = "github:nixos/nixpkgs/nixos-unstable";
+    };"#
+        ));
+    }
 
-    buf.trim().to_string()
+    #[test]
+    fn write_records_history_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        let mut pins = Pins::default();
+        pins.sources
+            .insert("nixpkgs".into(), github("nixos", "nixpkgs", REV, HASH));
+        pins.remove("nixpkgs");
+
+        pins.write_to_file(&file, Context::default()).unwrap();
+        pins.write_to_file(&file, Context::default()).unwrap();
+        assert!(pins.changes.is_empty());
+        assert_eq!(History::for_pins_file(&file).read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn read_errors_keep_path() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        match Pins::read_from_file(&file, Arc::default()) {
+            Err(Error::Read { path, .. }) => assert_eq!(path, file),
+            other => panic!("expected a read error, got {other:?}"),
+        }
+
+        std::fs::write(&file, "{ inputs = ").unwrap();
+        match Pins::read_from_file(&file, Arc::default()) {
+            Err(Error::InvalidFile { path, .. }) => assert_eq!(path, file),
+            other => panic!("expected a parse error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn read_escaped_strings() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        let reason = r#"waiting for #123: "fix" in C:\nix ${builtins.abort "evaluated"}"#;
+
+        let mut pins = Pins::default();
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+        pins.freeze("nixpkgs", Some(reason.into())).unwrap();
+        pins.ignore("nixpkgs", "v1 {x}: y".into()).unwrap();
+        pins.write_to_file(&file, Context::default()).unwrap();
+
+        let pins = Pins::read_from_file(&file, Arc::default()).unwrap();
+        let input = &pins.inputs["nixpkgs"];
+        assert_eq!(
+            input.freeze.as_ref().unwrap().reason.as_deref(),
+            Some(reason)
+        );
+        assert_eq!(input.ignore, ["v1 {x}: y"]);
+    }
+
+    #[test]
+    fn realise() {
+        let store = tempfile::tempdir().unwrap();
+        let source = |hash: &str| github("nixos", "nixpkgs", REV, hash);
+
+        // the session already prefetched the pinned revision, nothing is fetched
+        let session = Session::default();
+        let pre = Prefetched {
+            store_path: store.path().to_path_buf(),
+            ..prefetched(REV)
+        };
+        session.remember(format!("github:nixos/nixpkgs/{REV}"), pre);
+
+        let mut pins = Pins::default().with_session(Arc::new(session));
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        pins.inputs
+            .insert("nixpkgs".into(), Input::new(url.clone(), false));
+        pins.inputs
+            .insert("unfetched".into(), Input::new(url.clone(), false));
+        pins.inputs.insert("stale".into(), Input::new(url, false));
+        pins.sources.insert("nixpkgs".into(), source(HASH));
+        pins.sources.insert("stale".into(), source("sha256-stale"));
+
+        assert_eq!(pins.realise("nixpkgs").unwrap().store_path, store.path());
+        assert!(matches!(
+            pins.realise("stale"),
+            Err(Error::HashMismatch { name, .. }) if name == "stale"
+        ));
+        assert!(matches!(
+            pins.realise("unfetched"),
+            Err(Error::MissingSource { .. })
+        ));
+        assert!(matches!(
+            pins.realise("missing"),
+            Err(Error::PinNotFound { .. })
+        ));
+
+        let filter = Filter {
+            pattern: Some("*n*".into()),
+            ..Filter::default()
+        };
+        let paths = pins.realise_all(&filter).unwrap();
+        assert_eq!(
+            paths,
+            BTreeMap::from([("nixpkgs".to_string(), store.path().to_path_buf())])
+        );
+        assert!(pins.realise_all(&Filter::default()).is_err());
+    }
+
+    #[test]
+    fn unchanged_ref() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let tagged = repo.commit("tagged");
+        repo.git(&["tag", "--annotate", "-m", "release", "release"]);
+        repo.git(&["tag", "--annotate", "-m", "release", "v1.0"]);
+        let branch = repo.commit("branch");
+        repo.git(&["branch", "release"]);
+        repo.commit("head");
+
+        let mut pins = Pins::default().with_session(Arc::new(repo.session()));
+        for (name, reference) in [("release", "release"), ("tagged", "v1.0")] {
+            let url = Url::try_from(format!("github:corp/tools/{reference}")).unwrap();
+            pins.inputs.insert(name.into(), Input::new(url, false));
+        }
+        let unchanged = |pins: &mut Pins, name: &str, rev: &str| {
+            pins.sources
+                .insert(name.into(), github("corp", "tools", rev, HASH));
+            pins.is_unchanged(name, &pins.inputs[name])
+        };
+
+        // a branch and a tag of the same name track the branch
+        assert!(unchanged(&mut pins, "release", &branch));
+        assert!(!unchanged(&mut pins, "release", &tagged));
+        // annotated tags are compared by the commit they point to
+        assert!(unchanged(&mut pins, "tagged", &tagged));
+        assert!(!unchanged(&mut pins, "tagged", &branch));
+    }
+
+    #[test]
+    fn min_age() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let now = crate::cache::now().unwrap();
+        let day = 86400;
+        let old = repo.commit_at("old", now - 30 * day);
+        let middle = repo.commit_at("middle", now - 10 * day);
+        let new = repo.commit_at("new", now - day);
+
+        let mut pins = Pins::default().with_session(Arc::new(repo.session()));
+        let url = Url::try_from("github:corp/tools".to_string()).unwrap();
+        pins.inputs.insert("tools".into(), Input::new(url, false));
+        let mut head = prefetched(&new);
+        head.locked.last_modified = now - day;
+        let min_age = |pins: &Pins, days: u64| {
+            pins.min_age_rev("tools", &pins.inputs["tools"], &head, days)
+                .unwrap()
+        };
+
+        assert_eq!(min_age(&pins, 0), MinAge::Satisfied);
+        // the newest old enough commit when nothing is pinned yet
+        assert_eq!(min_age(&pins, 5), MinAge::Older(middle.clone()));
+        assert_eq!(min_age(&pins, 60), MinAge::Hold);
+        assert_eq!(min_age(&pins, u64::MAX), MinAge::Hold);
+
+        pins.sources
+            .insert("tools".into(), github("corp", "tools", &old, HASH));
+        assert_eq!(min_age(&pins, 5), MinAge::Older(middle.clone()));
+        // the pinned revision is kept rather than replaced by an older one
+        pins.sources
+            .insert("tools".into(), github("corp", "tools", &middle, HASH));
+        assert_eq!(min_age(&pins, 5), MinAge::Hold);
+        assert_eq!(min_age(&pins, 20), MinAge::Hold);
+
+        // pins of an exact revision are never held back
+        let url = Url::try_from(format!("github:corp/tools/{new}")).unwrap();
+        pins.inputs.insert("tools".into(), Input::new(url, false));
+        assert_eq!(min_age(&pins, 5), MinAge::Satisfied);
+    }
+
+    #[test]
+    fn offline() {
+        let tmp = tempfile::tempdir().unwrap();
+        let session = Session::default()
+            .with_offline(true)
+            .with_cache_dir(tmp.path().to_path_buf());
+        let mut pins = Pins::default().with_session(Arc::new(session));
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+
+        assert!(matches!(
+            pins.add(url.clone(), None, false, vec![]),
+            Err(Error::Offline { .. })
+        ));
+        assert!(pins.inputs.is_empty());
+        assert!(pins.changes.is_empty());
+
+        let source = github("nixos", "nixpkgs", REV, HASH);
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+        pins.sources.insert("nixpkgs".into(), source.clone());
+        assert!(matches!(
+            pins.update("nixpkgs", &UpdatePolicy::default()),
+            Err(Error::Offline { .. })
+        ));
+        assert!(matches!(pins.verify("nixpkgs"), Err(Error::Offline { .. })));
+        assert_eq!(pins.sources["nixpkgs"], source);
+        assert!(pins.changes.is_empty());
+    }
+
+    #[test]
+    fn verify_cached() {
+        let tmp = tempfile::tempdir().unwrap();
+        let session = Session::default()
+            .with_offline(true)
+            .with_cache_dir(tmp.path().to_path_buf());
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        let pinned = Url {
+            tag: Some(REV.into()),
+            ..url.clone()
+        };
+        session.store(&pinned, &prefetched(REV)).unwrap();
+
+        let mut pins = Pins::default().with_session(Arc::new(session));
+        for (name, rev, hash) in [
+            ("nixpkgs", REV, HASH),
+            ("stale", REV, "sha256-stale"),
+            ("uncached", "1111111111111111111111111111111111111111", HASH),
+        ] {
+            pins.inputs
+                .insert(name.into(), Input::new(url.clone(), false));
+            pins.sources
+                .insert(name.into(), github("nixos", "nixpkgs", rev, hash));
+        }
+
+        assert_eq!(pins.verify_local("nixpkgs").unwrap(), Some(true));
+        assert_eq!(pins.verify_local("stale").unwrap(), Some(false));
+        assert_eq!(pins.verify_local("uncached").unwrap(), None);
+        assert!(matches!(
+            pins.verify_local("missing"),
+            Err(Error::PinNotFound { .. })
+        ));
+    }
+
+    /// Pins of `github:corp/tools` fetched from `repo`, with its head already prefetched
+    fn local_pins(repo: &Repo, pinned: &str) -> Pins {
+        let session = repo.session();
+        let url = Url::try_from("github:corp/tools".to_string()).unwrap();
+        let head = repo.git(&["rev-parse", "HEAD"]);
+        session.remember(url.flake_ref(session.rules()).unwrap(), prefetched(&head));
+
+        let mut pins = Pins::default().with_session(Arc::new(session));
+        pins.inputs.insert("tools".into(), Input::new(url, false));
+        pins.sources
+            .insert("tools".into(), github("corp", "tools", pinned, HASH));
+        pins
+    }
+
+    #[test]
+    fn skip_frozen_and_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let pinned = repo.commit("pinned");
+        let head = repo.commit("head");
+        let policy = UpdatePolicy::default();
+
+        let mut pins = local_pins(&repo, &pinned);
+        assert_eq!(
+            pins.resolve("tools", &policy)
+                .unwrap()
+                .unwrap()
+                .fetcher
+                .version(),
+            head
+        );
+
+        pins.freeze("tools", Some("waiting for #123".into()))
+            .unwrap();
+        assert!(pins.resolve("tools", &policy).unwrap().is_none());
+        assert!(matches!(
+            pins.update("tools", &policy),
+            Err(Error::Frozen { .. })
+        ));
+        pins.unfreeze("tools").unwrap();
+
+        pins.ignore("tools", git::short_rev(&head).into()).unwrap();
+        assert!(pins.resolve("tools", &policy).unwrap().is_none());
+        pins.unignore("tools", git::short_rev(&head)).unwrap();
+
+        repo.git(&["tag", "--annotate", "-m", "broken", "v2.0"]);
+        pins.ignore("tools", "v2.0".into()).unwrap();
+        pins.update_all(&["tools".into()], 1, &policy).unwrap();
+        assert_eq!(pins.sources["tools"].version(), pinned);
+        assert!(pins.changes.is_empty());
+    }
+
+    #[test]
+    fn hold_until_updated_by_name() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::init(&tmp.path().join("repo"));
+        let pinned = repo.commit("pinned");
+        let held = repo.commit("held");
+        let head = repo.commit("head");
+        let policy = UpdatePolicy::default();
+
+        let mut pins = local_pins(&repo, &pinned);
+        let url = Url {
+            tag: Some(held.clone()),
+            ..pins.inputs["tools"].url.clone()
+        };
+        let flake_ref = url.flake_ref(pins.session().rules()).unwrap();
+        pins.session().remember(flake_ref, prefetched(&held));
+
+        pins.update_to_rev("tools", git::short_rev(&held)).unwrap();
+        assert_eq!(pins.inputs["tools"].hold.as_deref(), Some(held.as_str()));
+        assert_eq!(pins.sources["tools"].version(), held);
+
+        // held pins are skipped by updates of every pin
+        assert!(pins.resolve("tools", &policy).unwrap().is_none());
+        pins.update_all(&["tools".into()], 1, &policy).unwrap();
+        assert_eq!(pins.sources["tools"].version(), held);
+
+        // and released by updating them by name
+        pins.update("tools", &policy).unwrap();
+        assert_eq!(pins.inputs["tools"].hold, None);
+        assert_eq!(pins.sources["tools"].version(), head);
+    }
+
+    #[test]
+    fn rename_history() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        let source = |rev: &str| github("nixos", "nixpkgs", rev, HASH);
+
+        let mut pins = Pins::default();
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+        pins.set_source("nixpkgs", source("0000000000"));
+        pins.write_to_file(&file, Context::default()).unwrap();
+
+        pins.set_source("nixpkgs", source("1111111111"));
+        pins.rename("nixpkgs", "unstable").unwrap();
+        pins.write_to_file(&file, Context::default()).unwrap();
+
+        let history = History::for_pins_file(&file);
+        assert!(history.entries_for("nixpkgs").unwrap().is_empty());
+        let revs = history
+            .entries_for("unstable")
+            .unwrap()
+            .iter()
+            .map(|entry| entry.revs())
+            .collect::<Vec<_>>();
+        assert_eq!(revs, ["(added) -> 0000000", "0000000 -> 1111111"]);
+    }
+
+    #[test]
+    fn write_each_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        let source = |rev: &str| github("nixos", "nixpkgs", rev, HASH);
+
+        let mut pins = Pins::default();
+        for name in ["nixpkgs", "unstable"] {
+            let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+            pins.inputs.insert(name.into(), Input::new(url, false));
+            pins.set_source(name, source("0000000000"));
+        }
+        pins.write_to_file(&file, Context::default()).unwrap();
+
+        pins.set_source("nixpkgs", source("1111111111"));
+        pins.set_source("unstable", source("2222222222"));
+        let mut written = vec![];
+        pins.write_each_change(&file, Context::default(), |entry| {
+            let pins = Pins::read_from_file(&file, Arc::default())?;
+            let versions = ["nixpkgs", "unstable"].map(|name| pins.sources[name].version());
+            written.push((entry.name.clone(), versions.join(" ")));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            written,
+            [
+                ("nixpkgs".into(), "1111111111 0000000000".into()),
+                ("unstable".into(), "1111111111 2222222222".into())
+            ]
+        );
+        assert!(pins.changes().is_empty());
+        assert_eq!(History::for_pins_file(&file).read().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn rename_and_set() {
+        let mut pins = Pins::default();
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        pins.inputs.insert("nixpkgs".into(), Input::new(url, false));
+
+        pins.rename("nixpkgs", "unstable").unwrap();
+        assert!(pins.rename("nixpkgs", "stable").is_err());
+        assert_eq!(pins.renamed, [("nixpkgs".into(), "unstable".into())]);
+        assert!(pins.inputs.contains_key("unstable"));
+
+        let set_ref = |pins: &mut Pins, reference: &str| {
+            pins.set("unstable", None, Some(reference.into()), None)
+                .unwrap()
+        };
+        assert!(set_ref(&mut pins, "nixos-unstable"));
+        assert!(!set_ref(&mut pins, "nixos-unstable"));
+        assert_eq!(
+            pins.inputs["unstable"].url.fmt().unwrap(),
+            "github:nixos/nixpkgs/nixos-unstable"
+        );
+        assert!(set_ref(&mut pins, ""));
+        assert_eq!(pins.inputs["unstable"].url.tag, None);
+    }
+
+    #[test]
+    fn read_legacy_flake() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file = tmp.path().join("pins.nix");
+        std::fs::write(
+            &file,
+            r#"# this file was generated by nixpins (https://github.com/juliamertz/nixpins)
+{ ... }:
+let fetchFlake = attrs: builtins.getFlake "${attrs.url}/${attrs.rev}"; in
+{
+  inputs = {
+    nixpins = { url = "github:juliamertz/nixpins"; flake = true; };
+  };
+  sources = {
+    nixpins = fetchFlake {
+      url = "github:juliamertz/nixpins";
+      rev = "0123456789abcdef0123456789abcdef01234567";
+      hash = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+    };
+  };
+}
+"#,
+        )
+        .unwrap();
+
+        let pins = Pins::read_from_file(&file, Arc::default()).unwrap();
+        let Fetcher::Flake(flake) = &pins.sources["nixpins"] else {
+            panic!("expected a flake source");
+        };
+        assert_eq!(flake.r#type, "github");
+        assert_eq!(flake.owner.as_deref(), Some("juliamertz"));
+        assert_eq!(flake.repo.as_deref(), Some("nixpins"));
+        assert_eq!(flake.url, None);
+        assert_eq!(
+            flake.hash(),
+            "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        );
+        assert!(pins
+            .emit(Context::default())
+            .contains(r#"type = "github";"#));
+    }
 }
@@ -1,34 +1,36 @@
-use anyhow::Result;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{path::PathBuf, process::Command};
 
 use crate::{
-    fetcher::{self, Fetcher, Source},
-    url::Url,
+    session::Session,
+    url::{self, Url},
+    Error, Result,
 };
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Locked {
     pub last_modified: i64,
     pub nar_hash: Option<String>,
-    pub owner: String,
-    pub repo: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub url: Option<String>,
     pub rev: String,
     pub r#type: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[allow(dead_code)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Original {
-    pub owner: String,
-    pub repo: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
     pub r#type: String,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Prefetched {
     pub hash: String,
@@ -37,18 +39,43 @@ pub struct Prefetched {
     pub store_path: PathBuf,
 }
 
-impl From<Prefetched> for Fetcher {
-    fn from(pre: Prefetched) -> Self {
-        match pre.original.r#type.as_str() {
-            "github" => Fetcher::Github(fetcher::github::Github::from_prefetched(pre)),
-            "gitlab" => Fetcher::Gitlab(fetcher::gitlab::Gitlab::from_prefetched(pre)),
-            _ => unimplemented!(),
+/// Prefetch the source of `url`, reusing earlier results of the session and the prefetch cache
+pub fn prefetch_url(session: &Session, url: &Url) -> Result<Prefetched> {
+    let flake_ref = url.flake_ref(session.rules())?;
+    if let Some(pre) = session.prefetched(&flake_ref) {
+        log::debug!("Reusing prefetched {flake_ref}");
+        return Ok(pre);
+    }
+    // an unusable cache only costs a download, only a miss while offline is fatal
+    match session.cached(url) {
+        Ok(Some(pre)) => {
+            log::debug!("Using cached {flake_ref} at {rev}", rev = pre.locked.rev);
+            session.remember(flake_ref, pre.clone());
+            return Ok(pre);
         }
+        Ok(None) => {}
+        Err(err) => log::warn!("Ignoring the prefetch cache for {flake_ref}: {err}"),
     }
+
+    let pre = refetch(session, url)?;
+    session.remember(flake_ref, pre.clone());
+    Ok(pre)
 }
 
-pub fn prefetch_url(url: &Url) -> Result<Prefetched> {
-    let output = Command::new("nix")
+/// Prefetch a source without consulting any cached result,
+/// recording the result in the prefetch cache
+pub fn refetch(session: &Session, url: &Url) -> Result<Prefetched> {
+    let remote = url.fetch_remote(session.rules());
+    let flake_ref = url.flake_ref(session.rules())?;
+    session.ensure_online(&format!("prefetch {flake_ref}"))?;
+    let mut command = Command::new("nix");
+    if let Some(settings) = session.auth().nix_config(url::remote_domain(&remote)) {
+        // passed through the environment to keep the tokens out of the process list
+        let config = std::env::var("NIX_CONFIG").unwrap_or_default();
+        command.env("NIX_CONFIG", format!("{config}\n{settings}"));
+    }
+
+    let output = command
         .args([
             "flake",
             "prefetch",
@@ -56,14 +83,24 @@ pub fn prefetch_url(url: &Url) -> Result<Prefetched> {
             "--extra-experimental-features",
             "'nix-command flakes'",
             "--json",
-            &url.fmt()?,
+            &flake_ref,
         ])
         .output()?;
+    if !output.status.success() {
+        return Err(Error::Prefetch {
+            flake_ref,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
     let stdout = stdout.to_string();
 
-    println!("{}", &stdout);
+    log::debug!("{stdout}");
 
-    Ok(serde_json::from_str(stdout.as_ref())?)
+    let pre: Prefetched = serde_json::from_str(stdout.as_ref())?;
+    if let Err(err) = session.store(url, &pre) {
+        log::warn!("Unable to cache {flake_ref}: {err}");
+    }
+    Ok(pre)
 }
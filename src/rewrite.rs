@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+
+/// Replaces the start of a git remote, like git's `url.<to>.insteadOf = <from>`
+///
+/// For example:
+/// ```toml
+/// [[rewrite]]
+/// from = "https://github.com/"
+/// to = "https://git.mirror.corp/github/"
+/// emit = true
+/// ```
+///
+/// Archives of a provider can't be downloaded through a git mirror, so pins with a rewritten
+/// remote are prefetched with git and emitted as `fetchGit` whether or not `emit` is set.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub from: String,
+    pub to: String,
+    /// Also fetch through the rewritten remote when evaluating pins.nix,
+    /// instead of the upstream remote with the prefetched hash
+    #[serde(default)]
+    pub emit: bool,
+}
+
+/// Rewrite `remote` with the rule matching the longest prefix,
+/// the first declared rule wins between equally long prefixes
+pub fn apply<'a>(rules: &'a [Rule], remote: &str) -> Option<(String, &'a Rule)> {
+    let mut best: Option<&Rule> = None;
+    for rule in rules {
+        if !remote.starts_with(&rule.from) {
+            continue;
+        }
+        match best {
+            Some(b) if b.from.len() >= rule.from.len() => {}
+            _ => best = Some(rule),
+        }
+    }
+
+    best.map(|rule| (format!("{}{}", rule.to, &remote[rule.from.len()..]), rule))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{emit::Context, testing, url::Url, Fetcher, Session, Source};
+
+    fn rule(from: &str, to: &str) -> Rule {
+        Rule {
+            from: from.into(),
+            to: to.into(),
+            emit: false,
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let rules = [
+            rule("https://github.com/", "https://mirror.corp/github/"),
+            rule("https://github.com/nixos/", "https://nixos-mirror.corp/"),
+            rule("https://github.com/", "https://other.corp/"),
+            rule("https://gitlab.com/", "https://mirror.corp/gitlab/"),
+        ];
+        let rewrite = |remote: &str| apply(&rules, remote).map(|(remote, _)| remote);
+
+        assert_eq!(
+            rewrite("https://github.com/nixos/nixpkgs").as_deref(),
+            Some("https://nixos-mirror.corp/nixpkgs")
+        );
+        // equally long prefixes use the first rule
+        assert_eq!(
+            rewrite("https://github.com/nix-community/home-manager").as_deref(),
+            Some("https://mirror.corp/github/nix-community/home-manager")
+        );
+        assert_eq!(
+            rewrite("https://gitlab.com/corp/tools").as_deref(),
+            Some("https://mirror.corp/gitlab/corp/tools")
+        );
+        assert_eq!(rewrite("https://codeberg.org/corp/tools"), None);
+        assert_eq!(rewrite("ssh://git@github.com/corp/tools"), None);
+    }
+
+    #[test]
+    fn rewritten_sources_are_fetched_with_git() {
+        let url = Url::try_from("github:nixos/nixpkgs".to_string()).unwrap();
+        let pre = testing::prefetched(testing::REV);
+        let emitted_url = |session: &Session| match Fetcher::new(session, &url, pre.clone(), false)
+        {
+            Fetcher::Git(git) => Some(git.node().emit(Context::default())),
+            _ => None,
+        };
+
+        let session = Session::default();
+        assert_eq!(
+            url.flake_ref(session.rules()).unwrap(),
+            "github:nixos/nixpkgs"
+        );
+        assert_eq!(emitted_url(&session), None);
+
+        // prefetched from the mirror with git, emitted with the upstream remote
+        let session = Session::default().with_rules(vec![rule(
+            "https://github.com/",
+            "https://mirror.corp/github/",
+        )]);
+        assert_eq!(
+            url.flake_ref(session.rules()).unwrap(),
+            "git+https://mirror.corp/github/nixos/nixpkgs"
+        );
+        assert!(emitted_url(&session)
+            .unwrap()
+            .contains("url = \"https://github.com/nixos/nixpkgs\";"));
+
+        let session = Session::default().with_rules(vec![Rule {
+            emit: true,
+            ..rule("https://github.com/", "https://mirror.corp/github/")
+        }]);
+        assert!(emitted_url(&session)
+            .unwrap()
+            .contains("url = \"https://mirror.corp/github/nixos/nixpkgs\";"));
+    }
+}
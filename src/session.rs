@@ -0,0 +1,132 @@
+use crate::{
+    auth::Auth,
+    cache::{self, Cache},
+    fetcher::custom::Registry,
+    git,
+    prefetch::Prefetched,
+    rewrite::Rule,
+    url::Url,
+    Error, Result,
+};
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex};
+
+/// Settings and state shared by the fetches of a run: network access, credentials,
+/// rewrite rules, custom fetchers and the prefetch cache
+///
+/// Pins carry the session they fetch with, see [`Pins::with_session`](crate::Pins::with_session).
+#[derive(Debug, Default)]
+pub struct Session {
+    offline: bool,
+    auth: Auth,
+    rules: Vec<Rule>,
+    fetchers: Registry,
+    /// Seconds a resolved branch head is reused before prefetching it again
+    head_ttl: u64,
+    /// Directory of the prefetch cache and git mirrors, the user cache directory when unset
+    cache_dir: Option<PathBuf>,
+    /// Prefetch cache on disk, loaded on first use
+    cache: Mutex<Option<Cache>>,
+    /// Sources prefetched during this session by flake reference,
+    /// so pins sharing a url are only prefetched once
+    prefetched: Mutex<BTreeMap<String, Prefetched>>,
+}
+
+impl Session {
+    /// Make anything that needs network access fail, set by --offline
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_auth(mut self, auth: Auth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_rules(mut self, rules: Vec<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Fetch, emit and read sources with custom fetchers besides the builtin ones
+    pub fn with_fetchers(mut self, fetchers: Registry) -> Self {
+        self.fetchers = fetchers;
+        self
+    }
+
+    pub fn with_head_ttl(mut self, secs: u64) -> Self {
+        self.head_ttl = secs;
+        self
+    }
+
+    pub fn with_cache_dir(mut self, dir: PathBuf) -> Self {
+        self.cache_dir = Some(dir);
+        self
+    }
+
+    pub fn cache_dir(&self) -> Result<PathBuf> {
+        match &self.cache_dir {
+            Some(dir) => Ok(dir.clone()),
+            None => git::cache_dir(),
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline
+    }
+
+    /// Fail when network access is disabled by --offline
+    pub fn ensure_online(&self, action: &str) -> Result<()> {
+        if self.offline {
+            return Err(Error::Offline {
+                action: action.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn auth(&self) -> &Auth {
+        &self.auth
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn fetchers(&self) -> &Registry {
+        &self.fetchers
+    }
+
+    /// Source prefetched earlier in this session
+    pub fn prefetched(&self, flake_ref: &str) -> Option<Prefetched> {
+        self.prefetched.lock().unwrap().get(flake_ref).cloned()
+    }
+
+    /// Remember a prefetched source for the rest of this session
+    pub fn remember(&self, flake_ref: String, pre: Prefetched) {
+        self.prefetched.lock().unwrap().insert(flake_ref, pre);
+    }
+
+    /// Look up `url` in the prefetch cache
+    pub fn cached(&self, url: &Url) -> Result<Option<Prefetched>> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.is_none() {
+            *cache = Some(Cache::load(&self.cache_dir()?)?);
+        }
+        cache
+            .as_ref()
+            .unwrap()
+            .lookup(url, &self.rules, cache::now()?, self.head_ttl)
+    }
+
+    /// Record a prefetched source and write the cache to disk
+    pub fn store(&self, url: &Url, pre: &Prefetched) -> Result<()> {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.is_none() {
+            *cache = Some(Cache::load(&self.cache_dir()?)?);
+        }
+        let cache = cache.as_mut().unwrap();
+        cache.insert(url, &self.rules, pre, cache::now()?)?;
+        cache.save(&self.cache_dir()?)
+    }
+}
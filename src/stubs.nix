@@ -1,7 +1,9 @@
-let
   # passthrough args for enum deserialization with tvix
   enum = name: attrs: { ${name} = attrs; };
   fetchFromGitHub = enum "Github";
   fetchFromGitLab = enum "Gitlab";
+  fetchFromSourcehut = enum "Sourcehut";
+  fetchFromGitea = enum "Gitea";
+  fetchFromBitbucket = enum "Bitbucket";
+  fetchGit = enum "Git";
   fetchFlake = enum "Flake";
-in
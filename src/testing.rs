@@ -0,0 +1,111 @@
+//! Fixtures shared by the unit tests
+
+use crate::{fetcher::Fetcher, prefetch::Prefetched, rewrite::Rule, Session};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+pub const REV: &str = "0123456789abcdef0123456789abcdef01234567";
+pub const HASH: &str = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+/// Result of prefetching nixos/nixpkgs at `rev`, stored at a store path that doesn't exist
+pub fn prefetched(rev: &str) -> Prefetched {
+    serde_json::from_value(serde_json::json!({
+        "hash": HASH,
+        "locked": {
+            "lastModified": 1700000000,
+            "narHash": HASH,
+            "owner": "nixos",
+            "repo": "nixpkgs",
+            "rev": rev,
+            "type": "github"
+        },
+        "original": { "owner": "nixos", "repo": "nixpkgs", "type": "github" },
+        "storePath": "/nix/store/00000000000000000000000000000000-source"
+    }))
+    .unwrap()
+}
+
+/// Github source of `owner/repo` at `rev`
+pub fn github(owner: &str, repo: &str, rev: &str, hash: &str) -> Fetcher {
+    serde_json::from_value(serde_json::json!({
+        "Github": { "owner": owner, "repo": repo, "rev": rev, "hash": hash }
+    }))
+    .unwrap()
+}
+
+/// Git repository in a temporary directory, used as a `file://` remote
+pub struct Repo {
+    pub path: PathBuf,
+}
+
+impl Repo {
+    pub fn init(path: &Path) -> Self {
+        let repo = Self {
+            path: path.to_path_buf(),
+        };
+        let status = Command::new("git")
+            .args(["init", "--quiet"])
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        repo.git(&["config", "user.name", "nixpins"]);
+        repo.git(&["config", "user.email", "nixpins@example.com"]);
+        repo
+    }
+
+    pub fn remote(&self) -> String {
+        format!("file://{}", self.path.display())
+    }
+
+    /// Session fetching `github:corp/tools` from this repository,
+    /// caching next to it instead of the user cache directory
+    pub fn session(&self) -> Session {
+        Session::default()
+            .with_rules(vec![Rule {
+                from: "https://github.com/corp/tools".into(),
+                to: self.remote(),
+                emit: false,
+            }])
+            .with_cache_dir(self.path.with_extension("cache"))
+    }
+
+    /// Run git in the repository, returns its trimmed output
+    pub fn git(&self, args: &[&str]) -> String {
+        self.git_with(args, &[])
+    }
+
+    /// Create an empty commit, returns its revision
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["commit", "--quiet", "--allow-empty", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// Create an empty commit dated at `timestamp`, returns its revision
+    pub fn commit_at(&self, message: &str, timestamp: i64) -> String {
+        let date = format!("@{timestamp} +0000");
+        self.git_with(
+            &["commit", "--quiet", "--allow-empty", "-m", message],
+            &[("GIT_COMMITTER_DATE", &date), ("GIT_AUTHOR_DATE", &date)],
+        );
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    fn git_with(&self, args: &[&str], envs: &[(&str, &str)]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "git {args:?}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    config::{self, Config},
+    fetcher::Source,
+    filter, git,
+    pins::Pins,
+    session::Session,
+    Error, Result,
+};
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// A pins file of the workspace
+pub struct Member {
+    pub path: PathBuf,
+    pub pins: Pins,
+}
+
+/// Directory the workspace members are relative to: the directory of the config file,
+/// or the git repository root containing the current directory
+pub fn root(config: &Config) -> Result<PathBuf> {
+    if let Some(dir) = config.path.as_deref().and_then(Path::parent) {
+        return Ok(dir.to_path_buf());
+    }
+
+    let cwd = std::env::current_dir()?;
+    let root = cwd.ancestors().find(|dir| dir.join(".git").exists());
+    Ok(root.unwrap_or(&cwd).to_path_buf())
+}
+
+/// Read every pins file of the workspace, fetching with a shared session
+pub fn read(config: &Config, session: &Arc<Session>) -> Result<Vec<Member>> {
+    let root = root(config)?;
+    let file_name = config.file.file_name().unwrap_or(OsStr::new("pins.nix"));
+
+    discover(&root, &config.workspace, file_name)?
+        .into_iter()
+        .map(|path| {
+            let pins = Pins::read_from_file(&path, session.clone())?;
+            Ok(Member { path, pins })
+        })
+        .collect()
+}
+
+/// Find the pins files matching the member patterns, or every file named `file_name`
+/// below `root` when no members are declared
+pub fn discover(
+    root: &Path,
+    workspace: &config::Workspace,
+    file_name: &OsStr,
+) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    if workspace.members.is_empty() {
+        find_named(root, file_name, &mut paths)?;
+    }
+    for pattern in &workspace.members {
+        let segments = pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>();
+        expand(root, &segments, &mut paths)?;
+    }
+
+    paths.sort();
+    paths.dedup();
+    if paths.is_empty() {
+        return Err(Error::NotFound(format!(
+            "No pins files found in workspace {root:?}"
+        )));
+    }
+    Ok(paths)
+}
+
+/// Collect the files matching a glob pattern split into path segments
+fn expand(dir: &Path, segments: &[&str], paths: &mut Vec<PathBuf>) -> Result<()> {
+    let Some((segment, rest)) = segments.split_first() else {
+        if dir.is_file() {
+            paths.push(dir.to_path_buf());
+        }
+        return Ok(());
+    };
+
+    if !segment.contains(['*', '?']) {
+        return expand(&dir.join(segment), rest, paths);
+    }
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if filter::glob(segment, &name.to_string_lossy()) {
+            expand(&entry.path(), rest, paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// Collect every file named `file_name` below `dir`, skipping hidden directories
+fn find_named(dir: &Path, file_name: &OsStr, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if !entry.file_name().to_string_lossy().starts_with('.') {
+                find_named(&path, file_name, paths)?;
+            }
+        } else if entry.file_name() == file_name {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Ensure every shared input is locked to the same revision in all members that have it
+pub fn check_shared(members: &[Member], shared: &[String]) -> Result<()> {
+    let mut conflicts = vec![];
+    for name in shared {
+        let revs = members
+            .iter()
+            .filter_map(|member| {
+                let source = member.pins.sources.get(name)?;
+                Some((member.path.as_path(), source.version()))
+            })
+            .collect::<Vec<_>>();
+
+        let distinct = revs
+            .iter()
+            .map(|(path, rev)| (*rev, *path))
+            .collect::<BTreeMap<_, _>>();
+        if distinct.len() > 1 {
+            let locks = revs
+                .iter()
+                .map(|(path, rev)| format!("\n  {path:?}: {}", git::short_rev(rev)))
+                .collect::<String>();
+            conflicts.push(format!("{name} is locked to different revisions:{locks}"));
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(Error::Conflict(conflicts.join("\n")));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_members() {
+        let root = tempfile::tempdir().unwrap();
+        for path in [
+            "pins.nix",
+            "services/api/pins.nix",
+            "services/web/pins.nix",
+            "services/web/other.nix",
+            "tools/cli/nix/pins.nix",
+            ".direnv/pins.nix",
+        ] {
+            let path = root.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let relative = |paths: Vec<PathBuf>| {
+            paths
+                .into_iter()
+                .map(|p| p.strip_prefix(root.path()).unwrap().to_path_buf())
+                .collect::<Vec<_>>()
+        };
+        let file_name = OsStr::new("pins.nix");
+
+        let found = discover(root.path(), &config::Workspace::default(), file_name).unwrap();
+        assert_eq!(
+            relative(found),
+            [
+                "pins.nix",
+                "services/api/pins.nix",
+                "services/web/pins.nix",
+                "tools/cli/nix/pins.nix"
+            ]
+            .map(PathBuf::from)
+        );
+
+        let workspace = config::Workspace {
+            members: vec!["services/*/pins.nix".into(), "tools/*/nix/*.nix".into()],
+            shared: vec![],
+        };
+        let found = discover(root.path(), &workspace, file_name).unwrap();
+        assert_eq!(
+            relative(found),
+            [
+                "services/api/pins.nix",
+                "services/web/pins.nix",
+                "tools/cli/nix/pins.nix"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+}